@@ -0,0 +1,508 @@
+//! The syntax tree of a compiled template.
+//!
+//! A template's tree can be read with `Template::ast`, and walked with the
+//! `Visitor` and `VisitorMut` traits. This is useful for writing linters and
+//! other tools that need to inspect templates. A tree changed with a
+//! `VisitorMut` can be turned back into a template with
+//! `Context::compile_ast`.
+//!
+//! More fields may be added to the nodes, so they can't be built with struct
+//! literals. Use their `new` functions instead. A node made that way has no
+//! source and an empty span.
+
+use parser::Span;
+
+/// A node in a template's syntax tree.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Node {
+    Text(Text),
+    Variable(Variable),
+    Section(Section),
+    InvertedSection(Section),
+    Partial(Partial),
+    DynamicPartial(DynamicPartial),
+    Parent(Parent),
+    Block(Block),
+    Comment(Comment),
+    Delimiters(Delimiters),
+}
+
+/// Plain text that is rendered as is.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Text {
+    pub text: String,
+    pub span: Span,
+    _private: (),
+}
+
+impl Text {
+    pub fn new(text: String) -> Text {
+        Text { text: text, span: empty_span(), _private: () }
+    }
+}
+
+/// A `{{name}}`, `{{{name}}}` or `{{&name}}` tag.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Variable {
+    /// The dotted name of the variable, split on the dots. An empty path
+    /// refers to the current context, `{{.}}`.
+    pub path: Vec<String>,
+    /// The arguments given to a helper, like the `amount currency` of
+    /// `{{format_money amount currency}}`.
+    pub args: Vec<Argument>,
+    /// Whether or not the value is HTML escaped when it is rendered.
+    pub escaped: bool,
+    /// The filters the value is piped through, like the `upper` of
+    /// `{{name | upper}}`, in the order they are applied.
+    pub filters: Vec<Filter>,
+    /// The source of the tag.
+    pub tag: String,
+    pub span: Span,
+    _private: (),
+}
+
+impl Variable {
+    /// Creates an escaped variable with no arguments or filters.
+    pub fn new(path: Vec<String>) -> Variable {
+        Variable {
+            path: path,
+            args: Vec::new(),
+            escaped: true,
+            filters: Vec::new(),
+            tag: String::new(),
+            span: empty_span(),
+            _private: (),
+        }
+    }
+}
+
+/// An argument given to a helper.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Argument {
+    /// A value looked up in the data, like `amount`.
+    Path(Vec<String>),
+    /// A quoted string, like `"active"`.
+    Str(String),
+    /// A whole number, like `2`.
+    Int(i64),
+}
+
+/// A filter in a `{{name | filter:arg}}` tag.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Filter {
+    pub name: String,
+    /// The arguments after the name, separated by colons.
+    pub args: Vec<String>,
+}
+
+/// A `{{#name}}...{{/name}}` section, or a `{{^name}}...{{/name}}` inverted
+/// section.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Section {
+    pub path: Vec<String>,
+    /// The arguments given to a block helper, like the `status "active"` of
+    /// `{{#if_eq status "active"}}`.
+    pub args: Vec<Argument>,
+    pub children: Vec<Node>,
+    /// The source of the opening tag.
+    pub open_tag: String,
+    /// The source between the opening and closing tags.
+    pub source: String,
+    /// The source of the closing tag.
+    pub close_tag: String,
+    /// The opening delimiter in effect at the end of the section.
+    pub otag: String,
+    /// The closing delimiter in effect at the end of the section.
+    pub ctag: String,
+    pub span: Span,
+    _private: (),
+}
+
+impl Section {
+    /// Creates a section with no arguments. Since it has no source, a lambda
+    /// rendering it is given an empty string.
+    pub fn new(path: Vec<String>, children: Vec<Node>) -> Section {
+        Section {
+            path: path,
+            args: Vec::new(),
+            children: children,
+            open_tag: String::new(),
+            source: String::new(),
+            close_tag: String::new(),
+            otag: "{{".to_string(),
+            ctag: "}}".to_string(),
+            span: empty_span(),
+            _private: (),
+        }
+    }
+}
+
+/// A `{{> name}}` tag.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Partial {
+    pub name: String,
+    /// The whitespace the partial is indented with when it is standalone.
+    pub indent: String,
+    pub tag: String,
+    pub span: Span,
+    _private: (),
+}
+
+impl Partial {
+    pub fn new(name: String) -> Partial {
+        Partial {
+            name: name,
+            indent: String::new(),
+            tag: String::new(),
+            span: empty_span(),
+            _private: (),
+        }
+    }
+}
+
+/// A `{{>*name}}` tag, which renders the partial named by the value of
+/// `name` in the data.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DynamicPartial {
+    pub path: Vec<String>,
+    /// The whitespace the partial is indented with when it is standalone.
+    pub indent: String,
+    pub tag: String,
+    pub span: Span,
+    _private: (),
+}
+
+impl DynamicPartial {
+    pub fn new(path: Vec<String>) -> DynamicPartial {
+        DynamicPartial {
+            path: path,
+            indent: String::new(),
+            tag: String::new(),
+            span: empty_span(),
+            _private: (),
+        }
+    }
+}
+
+/// A `{{<parent}}...{{/parent}}` tag, which renders the `parent` partial
+/// with some of its blocks overridden.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Parent {
+    pub name: String,
+    /// The whitespace the parent is indented with when it is standalone.
+    pub indent: String,
+    /// The blocks that override those of the parent. Anything else is ignored.
+    pub children: Vec<Node>,
+    pub open_tag: String,
+    pub source: String,
+    pub close_tag: String,
+    pub span: Span,
+    _private: (),
+}
+
+impl Parent {
+    pub fn new(name: String, children: Vec<Node>) -> Parent {
+        Parent {
+            name: name,
+            indent: String::new(),
+            children: children,
+            open_tag: String::new(),
+            source: String::new(),
+            close_tag: String::new(),
+            span: empty_span(),
+            _private: (),
+        }
+    }
+}
+
+/// A `{{$block}}...{{/block}}` tag, whose children are rendered unless a
+/// parent overrides them.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Block {
+    pub name: String,
+    /// The whitespace the block is indented with when it is standalone.
+    pub indent: String,
+    pub children: Vec<Node>,
+    pub open_tag: String,
+    pub source: String,
+    pub close_tag: String,
+    pub span: Span,
+    _private: (),
+}
+
+impl Block {
+    pub fn new(name: String, children: Vec<Node>) -> Block {
+        Block {
+            name: name,
+            indent: String::new(),
+            children: children,
+            open_tag: String::new(),
+            source: String::new(),
+            close_tag: String::new(),
+            span: empty_span(),
+            _private: (),
+        }
+    }
+}
+
+/// A `{{! text}}` tag.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Comment {
+    pub text: String,
+    pub tag: String,
+    pub span: Span,
+    _private: (),
+}
+
+impl Comment {
+    pub fn new(text: String) -> Comment {
+        Comment { text: text, tag: String::new(), span: empty_span(), _private: () }
+    }
+}
+
+/// A `{{=otag ctag=}}` tag that changes the delimiters.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Delimiters {
+    pub otag: String,
+    pub ctag: String,
+    pub tag: String,
+    pub span: Span,
+    _private: (),
+}
+
+impl Delimiters {
+    pub fn new(otag: String, ctag: String) -> Delimiters {
+        Delimiters {
+            otag: otag,
+            ctag: ctag,
+            tag: String::new(),
+            span: empty_span(),
+            _private: (),
+        }
+    }
+}
+
+fn empty_span() -> Span {
+    Span { start: 0, end: 0, line: 0, col: 0 }
+}
+
+/// A `Visitor` walks a syntax tree. By default every node is visited, so
+/// implementations only need to override the methods for the nodes they are
+/// interested in.
+pub trait Visitor {
+    fn visit_node(&mut self, node: &Node) { walk_node(self, node) }
+    fn visit_text(&mut self, _text: &Text) { }
+    fn visit_variable(&mut self, _variable: &Variable) { }
+    fn visit_section(&mut self, section: &Section) { walk_section(self, section) }
+    fn visit_inverted_section(&mut self, section: &Section) { walk_section(self, section) }
+    fn visit_partial(&mut self, _partial: &Partial) { }
+    fn visit_dynamic_partial(&mut self, _partial: &DynamicPartial) { }
+    fn visit_parent(&mut self, parent: &Parent) { walk_nodes(self, &parent.children) }
+    fn visit_block(&mut self, block: &Block) { walk_nodes(self, &block.children) }
+    fn visit_comment(&mut self, _comment: &Comment) { }
+    fn visit_delimiters(&mut self, _delimiters: &Delimiters) { }
+}
+
+/// Visit each of the nodes in turn.
+pub fn walk_nodes<V: Visitor + ?Sized>(visitor: &mut V, nodes: &[Node]) {
+    for node in nodes.iter() {
+        visitor.visit_node(node);
+    }
+}
+
+/// Dispatch a node to the `Visitor` method for its type.
+pub fn walk_node<V: Visitor + ?Sized>(visitor: &mut V, node: &Node) {
+    match *node {
+        Node::Text(ref text) => visitor.visit_text(text),
+        Node::Variable(ref variable) => visitor.visit_variable(variable),
+        Node::Section(ref section) => visitor.visit_section(section),
+        Node::InvertedSection(ref section) => visitor.visit_inverted_section(section),
+        Node::Partial(ref partial) => visitor.visit_partial(partial),
+        Node::DynamicPartial(ref partial) => visitor.visit_dynamic_partial(partial),
+        Node::Parent(ref parent) => visitor.visit_parent(parent),
+        Node::Block(ref block) => visitor.visit_block(block),
+        Node::Comment(ref comment) => visitor.visit_comment(comment),
+        Node::Delimiters(ref delimiters) => visitor.visit_delimiters(delimiters),
+    }
+}
+
+/// Visit the children of a section.
+pub fn walk_section<V: Visitor + ?Sized>(visitor: &mut V, section: &Section) {
+    walk_nodes(visitor, &section.children)
+}
+
+/// A `VisitorMut` is like a `Visitor`, but it can modify the nodes as it
+/// walks the syntax tree.
+pub trait VisitorMut {
+    fn visit_node(&mut self, node: &mut Node) { walk_node_mut(self, node) }
+    fn visit_text(&mut self, _text: &mut Text) { }
+    fn visit_variable(&mut self, _variable: &mut Variable) { }
+    fn visit_section(&mut self, section: &mut Section) { walk_section_mut(self, section) }
+    fn visit_inverted_section(&mut self, section: &mut Section) { walk_section_mut(self, section) }
+    fn visit_partial(&mut self, _partial: &mut Partial) { }
+    fn visit_dynamic_partial(&mut self, _partial: &mut DynamicPartial) { }
+    fn visit_parent(&mut self, parent: &mut Parent) { walk_nodes_mut(self, &mut parent.children) }
+    fn visit_block(&mut self, block: &mut Block) { walk_nodes_mut(self, &mut block.children) }
+    fn visit_comment(&mut self, _comment: &mut Comment) { }
+    fn visit_delimiters(&mut self, _delimiters: &mut Delimiters) { }
+}
+
+/// Visit each of the nodes in turn.
+pub fn walk_nodes_mut<V: VisitorMut + ?Sized>(visitor: &mut V, nodes: &mut [Node]) {
+    for node in nodes.iter_mut() {
+        visitor.visit_node(node);
+    }
+}
+
+/// Dispatch a node to the `VisitorMut` method for its type.
+pub fn walk_node_mut<V: VisitorMut + ?Sized>(visitor: &mut V, node: &mut Node) {
+    match *node {
+        Node::Text(ref mut text) => visitor.visit_text(text),
+        Node::Variable(ref mut variable) => visitor.visit_variable(variable),
+        Node::Section(ref mut section) => visitor.visit_section(section),
+        Node::InvertedSection(ref mut section) => visitor.visit_inverted_section(section),
+        Node::Partial(ref mut partial) => visitor.visit_partial(partial),
+        Node::DynamicPartial(ref mut partial) => visitor.visit_dynamic_partial(partial),
+        Node::Parent(ref mut parent) => visitor.visit_parent(parent),
+        Node::Block(ref mut block) => visitor.visit_block(block),
+        Node::Comment(ref mut comment) => visitor.visit_comment(comment),
+        Node::Delimiters(ref mut delimiters) => visitor.visit_delimiters(delimiters),
+    }
+}
+
+/// Visit the children of a section.
+pub fn walk_section_mut<V: VisitorMut + ?Sized>(visitor: &mut V, section: &mut Section) {
+    walk_nodes_mut(visitor, &mut section.children)
+}
+
+#[cfg(test)]
+mod tests {
+    use context::Context;
+    use parser::Span;
+    use super::{Node, Text, Variable, Section, Partial, Visitor, VisitorMut, walk_nodes,
+                walk_nodes_mut};
+    use super::super::compile_str;
+
+    struct Names {
+        names: Vec<String>,
+    }
+
+    impl Visitor for Names {
+        fn visit_variable(&mut self, variable: &Variable) {
+            self.names.push(variable.path.connect("."));
+        }
+
+        fn visit_section(&mut self, section: &Section) {
+            self.names.push(section.path.connect("."));
+            super::walk_section(self, section);
+        }
+    }
+
+    /// Renames every variable to `name`, and adds a `{{> user}}` partial to
+    /// the end of each section.
+    struct Rename;
+
+    impl VisitorMut for Rename {
+        fn visit_variable(&mut self, variable: &mut Variable) {
+            variable.path = vec!("name".to_string());
+        }
+
+        fn visit_section(&mut self, section: &mut Section) {
+            super::walk_section_mut(self, section);
+            section.children.push(Node::Partial(Partial::new("user".to_string())));
+        }
+    }
+
+    struct Upcase;
+
+    #[derive(Encodable)]
+    struct Page { greeting: String, name: String, items: Vec<Item> }
+
+    #[derive(Encodable)]
+    struct Item { label: String }
+
+    impl VisitorMut for Upcase {
+        fn visit_text(&mut self, text: &mut Text) {
+            text.text = text.text.to_uppercase();
+        }
+    }
+
+    #[test]
+    fn test_ast() {
+        let template = compile_str("a {{b}}{{! c }}").unwrap();
+
+        let mut text = Text::new("a ".to_string());
+        text.span = Span { start: 0, end: 2, line: 1, col: 1 };
+
+        let mut variable = Variable::new(vec!("b".to_string()));
+        variable.tag = "{{b}}".to_string();
+        variable.span = Span { start: 2, end: 7, line: 1, col: 3 };
+
+        assert_eq!(template.ast()[..2].to_vec(), vec!(
+            Node::Text(text),
+            Node::Variable(variable)
+        ));
+
+        match template.ast()[2] {
+            Node::Comment(ref comment) => assert_eq!(comment.text, " c ".to_string()),
+            ref node => panic!("unexpected node {:?}", node),
+        }
+    }
+
+    #[test]
+    fn test_visitor() {
+        let template = compile_str("{{a}}{{#b}}{{{c.d}}}{{^e}}{{f}}{{/e}}{{/b}}").unwrap();
+
+        let mut visitor = Names { names: Vec::new() };
+        walk_nodes(&mut visitor, &template.ast());
+
+        assert_eq!(visitor.names, vec!(
+            "a".to_string(),
+            "b".to_string(),
+            "c.d".to_string(),
+            "f".to_string()
+        ));
+    }
+
+    #[test]
+    fn test_visitor_mut() {
+        let template = compile_str("a{{#b}}c{{/b}}").unwrap();
+
+        let mut nodes = template.ast();
+        walk_nodes_mut(&mut Upcase, &mut nodes);
+
+        match (&nodes[0], &nodes[1]) {
+            (&Node::Text(ref a), &Node::Section(ref section)) => {
+                assert_eq!(a.text, "A".to_string());
+                match section.children[0] {
+                    Node::Text(ref c) => assert_eq!(c.text, "C".to_string()),
+                    ref node => panic!("unexpected node {:?}", node),
+                }
+            }
+            _ => panic!(),
+        }
+    }
+
+    #[test]
+    fn test_compile_ast() {
+        let ctx = Context::new(Path::new("src/test-data"));
+        let template = ctx.compile("{{greeting}} {{#items}}{{label}}{{/items}}".chars()).unwrap();
+
+        let mut nodes = template.ast();
+        walk_nodes_mut(&mut Rename, &mut nodes);
+        let template = ctx.compile_ast(nodes).unwrap();
+
+        let page = Page {
+            greeting: "hi".to_string(),
+            name: "x".to_string(),
+            items: vec!(Item { label: "y".to_string() }, Item { label: "z".to_string() }),
+        };
+        let mut bytes = Vec::new();
+        template.render(&mut bytes, &page).unwrap();
+
+        // `{{label}}` became `{{name}}`, which is found outside of the items.
+        assert_eq!(
+            String::from_utf8(bytes).unwrap(),
+            "x x<strong>x</strong>\nx<strong>x</strong>\n".to_string());
+    }
+}
@@ -1,5 +1,4 @@
 use std::cell::RefCell;
-use std::collections::HashMap;
 use serialize::Encodable;
 use serialize::Encoder as Encoderer;
 
@@ -7,10 +6,19 @@ use encoder;
 use encoder::Encoder;
 use error::Error;
 use data::Data;
+use map::Map;
+use template::LambdaContext;
+
+type Lambda = FnMut(&mut LambdaContext) -> Result<String, Error>;
+
+#[cfg(feature = "serde")]
+use serde::Serialize;
+#[cfg(feature = "serde")]
+use serializer;
 
 /// `MapBuilder` is a helper type that construct `Data` types.
 pub struct MapBuilder {
-    data: HashMap<String, Data>,
+    data: Map,
 }
 
 impl MapBuilder {
@@ -18,7 +26,7 @@ impl MapBuilder {
     #[inline]
     pub fn new() -> MapBuilder {
         MapBuilder {
-            data: HashMap::new(),
+            data: Map::new(),
         }
     }
 
@@ -42,6 +50,16 @@ impl MapBuilder {
         Ok(MapBuilder { data: data })
     }
 
+    /// Add a serde `Serialize` value to the `MapBuilder`.
+    #[cfg(feature = "serde")]
+    #[inline]
+    pub fn insert_serde<T: Serialize>(self, key: String, value: &T) -> Result<MapBuilder, Error> {
+        let MapBuilder { mut data } = self;
+        let value = try!(serializer::serialize(value));
+        data.insert(key, value);
+        Ok(MapBuilder { data: data })
+    }
+
     /// Add a `String` to the `MapBuilder`.
     ///
     /// ```rust
@@ -72,6 +90,66 @@ impl MapBuilder {
         MapBuilder { data: data }
     }
 
+    /// Add an `i64` to the `MapBuilder`.
+    ///
+    /// ```rust
+    /// use mustache::MapBuilder;
+    /// let data = MapBuilder::new()
+    ///     .insert_int("balance".to_string(), -10)
+    ///     .build();
+    /// ```
+    #[inline]
+    pub fn insert_int(self, key: String, value: i64) -> MapBuilder {
+        let MapBuilder { mut data } = self;
+        data.insert(key, Data::Int(value));
+        MapBuilder { data: data }
+    }
+
+    /// Add a `u64` to the `MapBuilder`.
+    ///
+    /// ```rust
+    /// use mustache::MapBuilder;
+    /// let data = MapBuilder::new()
+    ///     .insert_uint("age".to_string(), 41)
+    ///     .build();
+    /// ```
+    #[inline]
+    pub fn insert_uint(self, key: String, value: u64) -> MapBuilder {
+        let MapBuilder { mut data } = self;
+        data.insert(key, Data::UInt(value));
+        MapBuilder { data: data }
+    }
+
+    /// Add an `f64` to the `MapBuilder`.
+    ///
+    /// ```rust
+    /// use mustache::MapBuilder;
+    /// let data = MapBuilder::new()
+    ///     .insert_float("height".to_string(), 1.65)
+    ///     .build();
+    /// ```
+    #[inline]
+    pub fn insert_float(self, key: String, value: f64) -> MapBuilder {
+        let MapBuilder { mut data } = self;
+        data.insert(key, Data::Float(value));
+        MapBuilder { data: data }
+    }
+
+    /// Add a null to the `MapBuilder`.
+    ///
+    /// ```rust
+    /// use mustache::MapBuilder;
+    /// let data = MapBuilder::new()
+    ///     .insert_null("spouse".to_string())
+    ///     .build();
+    /// ```
+    #[inline]
+    pub fn insert_null(self, key: String) -> MapBuilder {
+        let MapBuilder { mut data } = self;
+        data.insert(key, Data::Null);
+        MapBuilder { data: data }
+    }
+
     /// Add a `Vec` to the `MapBuilder`.
     ///
     /// ```rust
@@ -117,22 +195,51 @@ impl MapBuilder {
         MapBuilder { data: data }
     }
 
-    /// Add a function to the `MapBuilder`.
+    /// Add a function to the `MapBuilder`. It can change the state it
+    /// captures, like a counter.
     ///
     /// ```rust
     /// use mustache::MapBuilder;
     /// let mut count = 0;
     /// let data = MapBuilder::new()
-    ///     .insert_fn("increment", |_| {
+    ///     .insert_fn("increment".to_string(), move |_| {
     ///         count += 1u;
     ///         count.to_string()
     ///     })
     ///     .build();
     /// ```
     #[inline]
-    pub fn insert_fn<F>(self, key: String, f: F) -> MapBuilder where F:Fn(String) -> String + 'static {
+    pub fn insert_fn<F>(self, key: String, f: F) -> MapBuilder where F:FnMut(String) -> String + 'static {
         let MapBuilder { mut data } = self;
-        data.insert(key, Data::Fun(RefCell::new(Box::new(f) as Box<Fn(String) -> String>)));
+        data.insert(key, Data::Fun(RefCell::new(Box::new(f) as Box<FnMut(String) -> String>)));
+        MapBuilder { data: data }
+    }
+
+    /// Add a lambda to the `MapBuilder`. Unlike the functions added with
+    /// `insert_fn`, its output isn't compiled as a template, but it can
+    /// render templates itself with the `LambdaContext`.
+    ///
+    /// A lambda is borrowed mutably while it runs, so it can't be called
+    /// again until it returns. Rendering a section of the same lambda from
+    /// inside it, as `{{#bold}}a {{#bold}}b{{/bold}}{{/bold}}` does, fails
+    /// with `Error::RecursiveLambda`.
+    ///
+    /// ```rust
+    /// use mustache::MapBuilder;
+    /// let data = MapBuilder::new()
+    ///     .insert_lambda("bold".to_string(), |ctx| {
+    ///         let src = ctx.source().to_string();
+    ///         let text = try!(ctx.render(&src));
+    ///         Ok(format!("<b>{}</b>", text))
+    ///     })
+    ///     .build();
+    /// ```
+    #[inline]
+    pub fn insert_lambda<F>(self, key: String, f: F) -> MapBuilder
+        where F: FnMut(&mut LambdaContext) -> Result<String, Error> + 'static
+    {
+        let MapBuilder { mut data } = self;
+        data.insert(key, Data::Lambda(RefCell::new(Box::new(f) as Box<Lambda>)));
         MapBuilder { data: data }
     }
 
@@ -176,6 +283,16 @@ impl VecBuilder {
         Ok(VecBuilder { data: data })
     }
 
+    /// Add a serde `Serialize` value to the `VecBuilder`.
+    #[cfg(feature = "serde")]
+    #[inline]
+    pub fn push_serde<T: Serialize>(self, value: &T) -> Result<VecBuilder, Error> {
+        let VecBuilder { mut data } = self;
+        let value = try!(serializer::serialize(value));
+        data.push(value);
+        Ok(VecBuilder { data: data })
+    }
+
     /// Add a `String` to the `VecBuilder`.
     ///
     /// ```rust
@@ -208,6 +325,69 @@ impl VecBuilder {
         VecBuilder { data: data }
     }
 
+    /// Add an `i64` to the `VecBuilder`.
+    ///
+    /// ```rust
+    /// use mustache::VecBuilder;
+    /// let data = VecBuilder::new()
+    ///     .push_int(-1)
+    ///     .push_int(2)
+    ///     .build();
+    /// ```
+    #[inline]
+    pub fn push_int(self, value: i64) -> VecBuilder {
+        let VecBuilder { mut data } = self;
+        data.push(Data::Int(value));
+        VecBuilder { data: data }
+    }
+
+    /// Add a `u64` to the `VecBuilder`.
+    ///
+    /// ```rust
+    /// use mustache::VecBuilder;
+    /// let data = VecBuilder::new()
+    ///     .push_uint(1)
+    ///     .push_uint(2)
+    ///     .build();
+    /// ```
+    #[inline]
+    pub fn push_uint(self, value: u64) -> VecBuilder {
+        let VecBuilder { mut data } = self;
+        data.push(Data::UInt(value));
+        VecBuilder { data: data }
+    }
+
+    /// Add an `f64` to the `VecBuilder`.
+    ///
+    /// ```rust
+    /// use mustache::VecBuilder;
+    /// let data = VecBuilder::new()
+    ///     .push_float(0.5)
+    ///     .push_float(1.5)
+    ///     .build();
+    /// ```
+    #[inline]
+    pub fn push_float(self, value: f64) -> VecBuilder {
+        let VecBuilder { mut data } = self;
+        data.push(Data::Float(value));
+        VecBuilder { data: data }
+    }
+
+    /// Add a null to the `VecBuilder`.
+    ///
+    /// ```rust
+    /// use mustache::VecBuilder;
+    /// let data = VecBuilder::new()
+    ///     .push_null()
+    ///     .build();
+    /// ```
+    #[inline]
+    pub fn push_null(self) -> VecBuilder {
+        let VecBuilder { mut data } = self;
+        data.push(Data::Null);
+        VecBuilder { data: data }
+    }
+
     /// Add a `Vec` to the `MapBuilder`.
     ///
     /// ```rust
@@ -259,16 +439,35 @@ impl VecBuilder {
     /// use mustache::VecBuilder;
     /// let mut count = 0;
     /// let data = VecBuilder::new()
-    ///     .push_fn(|s| {
+    ///     .push_fn(move |s| {
     ///         count += 1u;
     ///         s + count.to_string()
     ///     })
     ///     .build();
     /// ```
     #[inline]
-    pub fn push_fn<F>(self, f: F) -> VecBuilder where F:Fn(String) -> String + 'static {
+    pub fn push_fn<F>(self, f: F) -> VecBuilder where F:FnMut(String) -> String + 'static {
+        let VecBuilder { mut data } = self;
+        data.push(Data::Fun(RefCell::new(Box::new(f) as Box<FnMut(String) -> String>)));
+        VecBuilder { data: data }
+    }
+
+    /// Add a lambda to the `VecBuilder`. Like the lambdas added with
+    /// `MapBuilder::insert_lambda`, it can't be called again while it runs,
+    /// so nesting it inside itself fails with `Error::RecursiveLambda`.
+    ///
+    /// ```rust
+    /// use mustache::VecBuilder;
+    /// let data = VecBuilder::new()
+    ///     .push_lambda(|ctx| Ok(ctx.source().trim().to_string()))
+    ///     .build();
+    /// ```
+    #[inline]
+    pub fn push_lambda<F>(self, f: F) -> VecBuilder
+        where F: FnMut(&mut LambdaContext) -> Result<String, Error> + 'static
+    {
         let VecBuilder { mut data } = self;
-        data.push(Data::Fun(RefCell::new(Box::new(f) as Box<Fn(String) -> String>)));
+        data.push(Data::Lambda(RefCell::new(Box::new(f) as Box<Lambda>)));
         VecBuilder { data: data }
     }
 
@@ -280,16 +479,15 @@ impl VecBuilder {
 
 #[cfg(test)]
 mod tests {
-    use std::collections::HashMap;
-
     use data::Data;
+    use map::Map;
     use super::{MapBuilder, VecBuilder};
 
     #[test]
     fn test_empty_builders() {
         assert_eq!(
             MapBuilder::new().build(),
-            Data::Map(HashMap::new()));
+            Data::Map(Map::new()));
 
         assert_eq!(
             VecBuilder::new().build(),
@@ -298,14 +496,14 @@ mod tests {
 
     #[test]
     fn test_builders() {
-        let mut pride_and_prejudice = HashMap::new();
+        let mut pride_and_prejudice = Map::new();
         pride_and_prejudice.insert("title".to_string(), Data::Str("Pride and Prejudice".to_string()));
-        pride_and_prejudice.insert("publish_date".to_string(), Data::Str("1813".to_string()));
+        pride_and_prejudice.insert("publish_date".to_string(), Data::UInt(1813));
 
-        let mut m = HashMap::new();
+        let mut m = Map::new();
         m.insert("first_name".to_string(), Data::Str("Jane".to_string()));
         m.insert("last_name".to_string(), Data::Str("Austen".to_string()));
-        m.insert("age".to_string(), Data::Str("41".to_string()));
+        m.insert("age".to_string(), Data::UInt(41));
         m.insert("died".to_string(), Data::Bool(true));
         m.insert("works".to_string(), Data::Vec(vec!(
             Data::Str("Sense and Sensibility".to_string()),
@@ -330,6 +528,29 @@ mod tests {
             Data::Map(m));
     }
 
+    #[test]
+    fn test_number_builders() {
+        let mut m = Map::new();
+        m.insert("int".to_string(), Data::Int(-1));
+        m.insert("uint".to_string(), Data::UInt(1));
+        m.insert("float".to_string(), Data::Float(0.5));
+        m.insert("null".to_string(), Data::Null);
+        m.insert("vec".to_string(), Data::Vec(vec!(
+            Data::Int(-1), Data::UInt(1), Data::Float(0.5), Data::Null)));
+
+        assert_eq!(
+            MapBuilder::new()
+                .insert_int("int", -1)
+                .insert_uint("uint", 1)
+                .insert_float("float", 0.5)
+                .insert_null("null")
+                .insert_vec("vec", |builder| {
+                    builder.push_int(-1).push_uint(1).push_float(0.5).push_null()
+                })
+                .build(),
+            Data::Map(m));
+    }
+
     #[test]
     fn test_map_fn_builder() {
         // We can't directly compare closures, so just make sure we thread
@@ -337,7 +558,7 @@ mod tests {
 
         let mut count = 0u;
         let data = MapBuilder::new()
-            .insert_fn("count", |s| {
+            .insert_fn("count", move |s| {
                 count += 1u;
                 s + count.to_string()
             })
@@ -366,7 +587,7 @@ mod tests {
 
         let mut count = 0u;
         let data = VecBuilder::new()
-            .push_fn(|s| {
+            .push_fn(move |s| {
                 count += 1u;
                 s + count.to_string()
             })
@@ -1,16 +1,17 @@
 use std::collections::HashMap;
-use std::io::Read;
-use std::fs::File;
+use std::rc::Rc;
 use std::str;
 
-use parser::{Parser, Token};
+use ast::{self, Visitor};
+use parser::{self, Parser, Token};
 use context::Context;
+use error::Error;
 
 /// `Compiler` is a object that compiles a string into a `Vec<Token>`.
 pub struct Compiler<T> {
     ctx: Context,
     reader: T,
-    partials: HashMap<String, Vec<Token>>,
+    partials: HashMap<String, Rc<Vec<Token>>>,
     otag: String,
     ctag: String,
 }
@@ -31,7 +32,7 @@ impl<T: Iterator<Item = char>> Compiler<T> {
     pub fn new_with(
         ctx: Context,
         reader: T,
-        partials: HashMap<String, Vec<Token>>,
+        partials: HashMap<String, Rc<Vec<Token>>>,
         otag: String,
         ctag: String
     ) -> Compiler<T> {
@@ -45,31 +46,37 @@ impl<T: Iterator<Item = char>> Compiler<T> {
     }
 
     /// Compiles a template into a series of tokens.
-    pub fn compile(mut self) -> (Vec<Token>, HashMap<String, Vec<Token>>) {
+    pub fn compile(mut self) -> Result<(Vec<Token>, HashMap<String, Rc<Vec<Token>>>), Error> {
         let (tokens, partials) = {
-            let parser = Parser::new(&mut self.reader, &self.otag, &self.ctag);
-            parser.parse()
+            let parser = Parser::new(&mut self.reader, &self.otag, &self.ctag)
+                .with_filters(self.ctx.has_filters())
+                .with_helpers(self.ctx.has_helpers());
+            try!(parser.parse())
         };
 
+        self.compile_partials(tokens, partials)
+    }
+
+    /// Compiles the partials used by some already parsed tokens.
+    fn compile_partials(
+        mut self,
+        tokens: Vec<Token>,
+        partials: Vec<String>
+    ) -> Result<(Vec<Token>, HashMap<String, Rc<Vec<Token>>>), Error> {
         // Compile the partials if we haven't done so already.
         for name in partials.into_iter() {
-            let path = self.ctx.template_path.join(&format!("{}.{}", name, self.ctx.template_extension));
-
             if !self.partials.contains_key(&name) {
                 // Insert a placeholder so we don't recurse off to infinity.
-                self.partials.insert(name.to_string(), Vec::new());
-
-                let mut string = String::new();
-
-                let mut file = match File::open(&path) {
-                    Ok(file) => file,
-                    Err(_) => panic!("error opening file: {:?}", path)
+                self.partials.insert(name.to_string(), Rc::new(Vec::new()));
+
+                // A missing partial renders as nothing, like a missing value,
+                // and keeps its empty placeholder. In strict mode it's an error.
+                let string = match self.ctx.load_partial(&name) {
+                    Ok(string) => string,
+                    Err(Error::PartialNotFound(_)) if !self.ctx.is_strict() => { continue; }
+                    Err(err) => { return Err(err); }
                 };
 
-                if let Err(_) = file.read_to_string(&mut string) {
-                    panic!("error reading file: {:?}", path)
-                }
-
                 let compiler = Compiler {
                     ctx: self.ctx.clone(),
                     reader: string.chars(),
@@ -78,31 +85,91 @@ impl<T: Iterator<Item = char>> Compiler<T> {
                     ctag: "}}".to_string(),
                 };
 
-                let (tokens, _) = compiler.compile();
+                // Keep the partials the partial used too, so that parents of
+                // parents can be found when rendering.
+                let (tokens, partials) = try!(compiler.compile());
 
-                self.partials.insert(name, tokens);
+                self.partials = partials;
+                self.partials.insert(name, Rc::new(tokens));
             }
         }
 
         let Compiler { partials, .. } = self;
 
-        (tokens, partials)
+        Ok((tokens, partials))
     }
 }
 
+/// Collects the names of the partials and parents a syntax tree uses, like
+/// the parser does for a template's source.
+struct PartialNames {
+    names: Vec<String>,
+}
+
+impl Visitor for PartialNames {
+    fn visit_partial(&mut self, partial: &ast::Partial) {
+        self.names.push(partial.name.clone());
+    }
+
+    fn visit_parent(&mut self, parent: &ast::Parent) {
+        if !parent.name.starts_with("*") {
+            self.names.push(parent.name.clone());
+        }
+        ast::walk_nodes(self, &parent.children);
+    }
+}
+
+/// Compiles a syntax tree into a series of tokens, along with the partials
+/// it uses.
+pub fn compile_ast(
+    ctx: Context,
+    nodes: &[ast::Node]
+) -> Result<(Vec<Token>, HashMap<String, Rc<Vec<Token>>>), Error> {
+    let mut names = PartialNames { names: Vec::new() };
+    ast::walk_nodes(&mut names, nodes);
+
+    Compiler::new(ctx, "".chars()).compile_partials(parser::from_nodes(nodes), names.names)
+}
+
 #[cfg(test)]
 mod tests {
+    use std::collections::HashMap;
+
+    use ast;
     use context::Context;
-    use parser::Token;
-    use parser::Token::{Text, ETag, UTag, Section, IncompleteSection, Partial};
+    use error::{Diagnostic, Error, ParseErrorKind};
+    use parser::{Span, Tag, Token};
+    use parser::Token::{Text, ETag, UTag, Section, IncompleteSection, Partial, DynamicPartial,
+                        Parent, Block, Comment, Delimiters};
     use super::Compiler;
 
+    // Most of these tests don't care about where tokens are, so their spans
+    // are ignored. See `test_compile_spans` for those.
+    const DUMMY_SPAN: Span = Span { start: 0, end: 0, line: 0, col: 0 };
+
+    fn context() -> Context {
+        let mut partials = HashMap::new();
+        partials.insert("test".to_string(), "{{foo}}bar".to_string());
+
+        Context::new(Path::new(".")).with_loader(partials)
+    }
+
     fn compile_str(template: &str) -> Vec<Token> {
-        let ctx = Context::new(Path::new("."));
-        let (tokens, _) = Compiler::new(ctx, template.chars()).compile();
+        let (tokens, _) = Compiler::new(context(), template.chars()).compile().unwrap();
         tokens
     }
 
+    fn compile_err(template: &str) -> Error {
+        match Compiler::new(context(), template.chars()).compile() {
+            Ok(_) => panic!("expected {:?} to fail to compile", template),
+            Err(err) => err,
+        }
+    }
+
+    fn tag_to_str(tag: &Tag) -> String {
+        format!("Tag(vec!({}), {:?}, {:?})", tag.path.connect(", "), tag.args, tag.filters)
+    }
+
     fn token_to_str(token: &Token) -> String {
         match *token {
             // recursive enums crash %?
@@ -113,11 +180,11 @@ mod tests {
                     ref osection,
                     ref src,
                     ref tag,
-                    ref ctag) => {
-                let name = name.iter().map(|e| format!("{}", *e)).collect::<Vec<String>>();
+                    ref ctag,
+                    _) => {
                 let children = children.iter().map(|x| token_to_str(x)).collect::<Vec<String>>();
-                format!("Section(vec!({}), {}, vec!({}), {}, {}, {}, {}, {})",
-                        name.connect(", "),
+                format!("Section({}, {}, vec!({}), {}, {}, {}, {}, {})",
+                        tag_to_str(name),
                         inverted,
                         children.connect(", "),
                         otag,
@@ -126,24 +193,53 @@ mod tests {
                         tag,
                         ctag)
             }
-            ETag(ref name, ref tag) => {
-                let name = name.iter().map(|e| format!("{}", *e)).collect::<Vec<String>>();
-                format!("ETag(vec!({}), {})", name.connect(", "), *tag)
+            Text(ref text, _) => {
+                format!("Text({})", *text)
             }
-            UTag(ref name, ref tag) => {
-                let name = name.iter().map(|e| format!("{}", *e)).collect::<Vec<String>>();
-                format!("UTag(vec!({}), {})", name.connect(", "), *tag)
+            ETag(ref name, ref tag, _) => {
+                format!("ETag({}, {})", tag_to_str(name), *tag)
             }
-            IncompleteSection(ref name, ref inverted, ref osection, ref newlined) => {
-                let name = name.iter().map(|e| format!("{}", *e)).collect::<Vec<String>>();
-                format!("IncompleteSection(vec!({}), {}, {}, {})",
-                        name.connect(", "),
-                        *inverted,
+            UTag(ref name, ref tag, _) => {
+                format!("UTag({}, {})", tag_to_str(name), *tag)
+            }
+            IncompleteSection(ref name, ref kind, ref osection, ref newlined, _) => {
+                format!("IncompleteSection({}, {:?}, {}, {})",
+                        tag_to_str(name),
+                        *kind,
                         *osection,
                         *newlined)
             }
-            _ => {
-                format!("{}", token)
+            Partial(ref name, ref indent, ref tag, _) => {
+                format!("Partial({}, {}, {})", *name, *indent, *tag)
+            }
+            DynamicPartial(ref path, ref indent, ref tag, _) => {
+                format!("DynamicPartial(vec!({}), {}, {})", path.connect(", "), *indent, *tag)
+            }
+            Parent(ref name, ref indent, ref children, ref osection, ref src, ref csection, _) => {
+                let children = children.iter().map(|x| token_to_str(x)).collect::<Vec<String>>();
+                format!("Parent({}, {}, vec!({}), {}, {}, {})",
+                        *name,
+                        *indent,
+                        children.connect(", "),
+                        *osection,
+                        *src,
+                        *csection)
+            }
+            Block(ref name, ref indent, ref children, ref osection, ref src, ref csection, _) => {
+                let children = children.iter().map(|x| token_to_str(x)).collect::<Vec<String>>();
+                format!("Block({}, {}, vec!({}), {}, {}, {})",
+                        *name,
+                        *indent,
+                        children.connect(", "),
+                        *osection,
+                        *src,
+                        *csection)
+            }
+            Comment(ref text, ref tag, _) => {
+                format!("Comment({}, {})", *text, *tag)
+            }
+            Delimiters(ref otag, ref ctag, ref tag, _) => {
+                format!("Delimiters({}, {}, {})", *otag, *ctag, *tag)
             }
         }
     }
@@ -159,182 +255,474 @@ mod tests {
     #[test]
     fn test_compile_texts() {
         check_tokens(compile_str("hello world"), &[
-            Text("hello world".to_string())
+            Text("hello world".to_string(), DUMMY_SPAN)
         ]);
         check_tokens(compile_str("hello {world"), &[
-            Text("hello {world".to_string())
+            Text("hello {world".to_string(), DUMMY_SPAN)
         ]);
         check_tokens(compile_str("hello world}"), &[
-            Text("hello world}".to_string())
+            Text("hello world}".to_string(), DUMMY_SPAN)
         ]);
         check_tokens(compile_str("hello world}}"), &[
-            Text("hello world}}".to_string())
+            Text("hello world}}".to_string(), DUMMY_SPAN)
         ]);
     }
 
     #[test]
     fn test_compile_etags() {
         check_tokens(compile_str("{{ name }}"), &[
-            ETag(vec!("name".to_string()), "{{ name }}".to_string())
+            ETag(
+                Tag::new(vec!("name".to_string())),
+                "{{ name }}".to_string(),
+                DUMMY_SPAN)
         ]);
 
         check_tokens(compile_str("before {{name}} after"), &[
-            Text("before ".to_string()),
-            ETag(vec!("name".to_string()), "{{name}}".to_string()),
-            Text(" after".to_string())
+            Text("before ".to_string(), DUMMY_SPAN),
+            ETag(
+                Tag::new(vec!("name".to_string())),
+                "{{name}}".to_string(),
+                DUMMY_SPAN),
+            Text(" after".to_string(), DUMMY_SPAN)
         ]);
 
         check_tokens(compile_str("before {{name}}"), &[
-            Text("before ".to_string()),
-            ETag(vec!("name".to_string()), "{{name}}".to_string())
+            Text("before ".to_string(), DUMMY_SPAN),
+            ETag(
+                Tag::new(vec!("name".to_string())),
+                "{{name}}".to_string(),
+                DUMMY_SPAN)
         ]);
 
         check_tokens(compile_str("{{name}} after"), &[
-            ETag(vec!("name".to_string()), "{{name}}".to_string()),
-            Text(" after".to_string())
+            ETag(
+                Tag::new(vec!("name".to_string())),
+                "{{name}}".to_string(),
+                DUMMY_SPAN),
+            Text(" after".to_string(), DUMMY_SPAN)
         ]);
     }
 
     #[test]
     fn test_compile_utags() {
         check_tokens(compile_str("{{{name}}}"), &[
-            UTag(vec!("name".to_string()), "{{{name}}}".to_string())
+            UTag(
+                Tag::new(vec!("name".to_string())),
+                "{{{name}}}".to_string(),
+                DUMMY_SPAN)
         ]);
 
         check_tokens(compile_str("before {{{name}}} after"), &[
-            Text("before ".to_string()),
-            UTag(vec!("name".to_string()), "{{{name}}}".to_string()),
-            Text(" after".to_string())
+            Text("before ".to_string(), DUMMY_SPAN),
+            UTag(
+                Tag::new(vec!("name".to_string())),
+                "{{{name}}}".to_string(),
+                DUMMY_SPAN),
+            Text(" after".to_string(), DUMMY_SPAN)
         ]);
 
         check_tokens(compile_str("before {{{name}}}"), &[
-            Text("before ".to_string()),
-            UTag(vec!("name".to_string()), "{{{name}}}".to_string())
+            Text("before ".to_string(), DUMMY_SPAN),
+            UTag(
+                Tag::new(vec!("name".to_string())),
+                "{{{name}}}".to_string(),
+                DUMMY_SPAN)
         ]);
 
         check_tokens(compile_str("{{{name}}} after"), &[
-            UTag(vec!("name".to_string()), "{{{name}}}".to_string()),
-            Text(" after".to_string())
+            UTag(
+                Tag::new(vec!("name".to_string())),
+                "{{{name}}}".to_string(),
+                DUMMY_SPAN),
+            Text(" after".to_string(), DUMMY_SPAN)
+        ]);
+    }
+
+    #[test]
+    fn test_compile_filters() {
+        let compile = |template: &str| {
+            Compiler::new(context().with_filters(), template.chars()).compile()
+        };
+
+        let (tokens, _) = compile("{{ name | upper | truncate:20:\"...\" }}").unwrap();
+        check_tokens(tokens, &[
+            ETag(
+                Tag {
+                    path: vec!("name".to_string()),
+                    args: Vec::new(),
+                    filters: vec!(
+                        ast::Filter { name: "upper".to_string(), args: Vec::new() },
+                        ast::Filter {
+                            name: "truncate".to_string(),
+                            args: vec!("20".to_string(), "...".to_string()),
+                        }
+                    ),
+                },
+                "{{ name | upper | truncate:20:\"...\" }}".to_string(),
+                DUMMY_SPAN)
+        ]);
+
+        let (tokens, _) = compile("{{{.|trim}}}").unwrap();
+        check_tokens(tokens, &[
+            UTag(
+                Tag {
+                    path: Vec::new(),
+                    args: Vec::new(),
+                    filters: vec!(ast::Filter { name: "trim".to_string(), args: Vec::new() }),
+                },
+                "{{{.|trim}}}".to_string(),
+                DUMMY_SPAN)
+        ]);
+
+        // Quoted arguments can contain the separators.
+        let (tokens, _) = compile("{{name | truncate:5:\"a:b\" | join:\" | \"}}").unwrap();
+        check_tokens(tokens, &[
+            ETag(
+                Tag {
+                    path: vec!("name".to_string()),
+                    args: Vec::new(),
+                    filters: vec!(
+                        ast::Filter {
+                            name: "truncate".to_string(),
+                            args: vec!("5".to_string(), "a:b".to_string()),
+                        },
+                        ast::Filter { name: "join".to_string(), args: vec!(" | ".to_string()) }
+                    ),
+                },
+                "{{name | truncate:5:\"a:b\" | join:\" | \"}}".to_string(),
+                DUMMY_SPAN)
+        ]);
+
+        assert_eq!(compile("{{name | truncate:5:\"a}}").err(), Some(Error::Parse {
+            kind: ParseErrorKind::InvalidArgument,
+            line: 1,
+            col: 1,
+            snippet: "{{name | truncate:5:\"a}}".to_string(),
+        }));
+
+        assert_eq!(compile("{{name | }}").err(), Some(Error::Parse {
+            kind: ParseErrorKind::EmptyFilter,
+            line: 1,
+            col: 1,
+            snippet: "{{name | }}".to_string(),
+        }));
+
+        // Without filters, the pipe is part of the name.
+        check_tokens(compile_str("{{a|b}}"), &[
+            ETag(
+                Tag::new(vec!("a|b".to_string())),
+                "{{a|b}}".to_string(),
+                DUMMY_SPAN)
         ]);
     }
 
     #[test]
     fn test_compile_sections() {
+        check_tokens(compile_str("{{#.}}{{.}}{{/.}}"), &[
+            Section(
+                Tag::new(Vec::new()),
+                false,
+                vec!(ETag(Tag::new(Vec::new()), "{{.}}".to_string(), DUMMY_SPAN)),
+                "{{".to_string(),
+                "{{#.}}".to_string(),
+                "{{.}}".to_string(),
+                "{{/.}}".to_string(),
+                "}}".to_string(),
+                DUMMY_SPAN
+            )
+        ]);
+
         check_tokens(compile_str("{{# name}}{{/name}}"), &[
             Section(
-                vec!("name".to_string()),
+                Tag::new(vec!("name".to_string())),
                 false,
                 Vec::new(),
                 "{{".to_string(),
                 "{{# name}}".to_string(),
                 "".to_string(),
                 "{{/name}}".to_string(),
-                "}}".to_string()
+                "}}".to_string(),
+                DUMMY_SPAN
             )
         ]);
 
         check_tokens(compile_str("before {{^name}}{{/name}} after"), &[
-            Text("before ".to_string()),
+            Text("before ".to_string(), DUMMY_SPAN),
             Section(
-                vec!("name".to_string()),
+                Tag::new(vec!("name".to_string())),
                 true,
                 Vec::new(),
                 "{{".to_string(),
                 "{{^name}}".to_string(),
                 "".to_string(),
                 "{{/name}}".to_string(),
-                "}}".to_string()
+                "}}".to_string(),
+                DUMMY_SPAN
             ),
-            Text(" after".to_string())
+            Text(" after".to_string(), DUMMY_SPAN)
         ]);
 
         check_tokens(compile_str("before {{#name}}{{/name}}"), &[
-            Text("before ".to_string()),
+            Text("before ".to_string(), DUMMY_SPAN),
             Section(
-                vec!("name".to_string()),
+                Tag::new(vec!("name".to_string())),
                 false,
                 Vec::new(),
                 "{{".to_string(),
                 "{{#name}}".to_string(),
                 "".to_string(),
                 "{{/name}}".to_string(),
-                "}}".to_string()
+                "}}".to_string(),
+                DUMMY_SPAN
             )
         ]);
 
         check_tokens(compile_str("{{#name}}{{/name}} after"), &[
             Section(
-                vec!("name".to_string()),
+                Tag::new(vec!("name".to_string())),
                 false,
                 Vec::new(),
                 "{{".to_string(),
                 "{{#name}}".to_string(),
                 "".to_string(),
                 "{{/name}}".to_string(),
-                "}}".to_string()
+                "}}".to_string(),
+                DUMMY_SPAN
             ),
-            Text(" after".to_string())
+            Text(" after".to_string(), DUMMY_SPAN)
         ]);
 
         check_tokens(compile_str(
                 "before {{#a}} 1 {{^b}} 2 {{/b}} {{/a}} after"), &[
-            Text("before ".to_string()),
+            Text("before ".to_string(), DUMMY_SPAN),
             Section(
-                vec!("a".to_string()),
+                Tag::new(vec!("a".to_string())),
                 false,
                 vec!(
-                    Text(" 1 ".to_string()),
+                    Text(" 1 ".to_string(), DUMMY_SPAN),
                     Section(
-                        vec!("b".to_string()),
+                        Tag::new(vec!("b".to_string())),
                         true,
-                        vec!(Text(" 2 ".to_string())),
+                        vec!(Text(" 2 ".to_string(), DUMMY_SPAN)),
                         "{{".to_string(),
                         "{{^b}}".to_string(),
                         " 2 ".to_string(),
                         "{{/b}}".to_string(),
-                        "}}".to_string()
+                        "}}".to_string(),
+                        DUMMY_SPAN
                     ),
-                    Text(" ".to_string())
+                    Text(" ".to_string(), DUMMY_SPAN)
                 ),
                 "{{".to_string(),
                 "{{#a}}".to_string(),
                 " 1 {{^b}} 2 {{/b}} ".to_string(),
                 "{{/a}}".to_string(),
-                "}}".to_string()
+                "}}".to_string(),
+                DUMMY_SPAN
             ),
-            Text(" after".to_string())
+            Text(" after".to_string(), DUMMY_SPAN)
         ]);
     }
 
     #[test]
     fn test_compile_partials() {
         check_tokens(compile_str("{{> test}}"), &[
-            Partial("test".to_string(), "".to_string(), "{{> test}}".to_string())
+            Partial("test".to_string(), "".to_string(), "{{> test}}".to_string(), DUMMY_SPAN)
         ]);
 
         check_tokens(compile_str("before {{>test}} after"), &[
-            Text("before ".to_string()),
-            Partial("test".to_string(), "".to_string(), "{{>test}}".to_string()),
-            Text(" after".to_string())
+            Text("before ".to_string(), DUMMY_SPAN),
+            Partial("test".to_string(), "".to_string(), "{{>test}}".to_string(), DUMMY_SPAN),
+            Text(" after".to_string(), DUMMY_SPAN)
         ]);
 
         check_tokens(compile_str("before {{> test}}"), &[
-            Text("before ".to_string()),
-            Partial("test".to_string(), "".to_string(), "{{> test}}".to_string())
+            Text("before ".to_string(), DUMMY_SPAN),
+            Partial("test".to_string(), "".to_string(), "{{> test}}".to_string(), DUMMY_SPAN)
         ]);
 
         check_tokens(compile_str("{{>test}} after"), &[
-            Partial("test".to_string(), "".to_string(), "{{>test}}".to_string()),
-            Text(" after".to_string())
+            Partial("test".to_string(), "".to_string(), "{{>test}}".to_string(), DUMMY_SPAN),
+            Text(" after".to_string(), DUMMY_SPAN)
+        ]);
+    }
+
+    #[test]
+    fn test_compile_dynamic_partials() {
+        let (tokens, partials) = Compiler::new(
+            context(),
+            "before {{>* widget.kind }} after".chars()
+        ).compile().unwrap();
+
+        check_tokens(tokens, &[
+            Text("before ".to_string(), DUMMY_SPAN),
+            DynamicPartial(
+                vec!("widget".to_string(), "kind".to_string()),
+                "".to_string(),
+                "{{>* widget.kind }}".to_string(),
+                DUMMY_SPAN),
+            Text(" after".to_string(), DUMMY_SPAN)
+        ]);
+
+        // Dynamic partials aren't loaded until they are rendered.
+        assert!(partials.is_empty());
+    }
+
+    #[test]
+    fn test_compile_comments() {
+        check_tokens(compile_str("before {{! note }} after"), &[
+            Text("before ".to_string(), DUMMY_SPAN),
+            Comment(" note ".to_string(), "{{! note }}".to_string(), DUMMY_SPAN),
+            Text(" after".to_string(), DUMMY_SPAN)
+        ]);
+
+        // Standalone comments still eat their line.
+        check_tokens(compile_str("before\n  {{! note }}\n{{! note }}\nafter"), &[
+            Text("before\n".to_string(), DUMMY_SPAN),
+            Comment(" note ".to_string(), "{{! note }}".to_string(), DUMMY_SPAN),
+            Comment(" note ".to_string(), "{{! note }}".to_string(), DUMMY_SPAN),
+            Text("after".to_string(), DUMMY_SPAN)
         ]);
     }
 
     #[test]
     fn test_compile_delimiters() {
         check_tokens(compile_str("before {{=<% %>=}}<%name%> after"), &[
-            Text("before ".to_string()),
-            ETag(vec!("name".to_string()), "<%name%>".to_string()),
-            Text(" after".to_string())
+            Text("before ".to_string(), DUMMY_SPAN),
+            Delimiters("<%".to_string(), "%>".to_string(), "{{=<% %>=}}".to_string(), DUMMY_SPAN),
+            ETag(
+                Tag::new(vec!("name".to_string())),
+                "<%name%>".to_string(),
+                DUMMY_SPAN),
+            Text(" after".to_string(), DUMMY_SPAN)
         ]);
     }
+
+    #[test]
+    fn test_compile_errors() {
+        assert_eq!(compile_err("hello {{name"), Error::Parse {
+            kind: ParseErrorKind::UnclosedTag,
+            line: 1,
+            col: 7,
+            snippet: "{{name".to_string(),
+        });
+
+        assert_eq!(compile_err("a\nb {{ }}"), Error::Parse {
+            kind: ParseErrorKind::EmptyTag,
+            line: 2,
+            col: 3,
+            snippet: "{{ }}".to_string(),
+        });
+
+        assert_eq!(compile_err("{{#a}}{{/b}}"), Error::Parse {
+            kind: ParseErrorKind::UnopenedSection("b".to_string()),
+            line: 1,
+            col: 7,
+            snippet: "{{/b}}".to_string(),
+        });
+
+        assert_eq!(compile_err("{{/a}}"), Error::Parse {
+            kind: ParseErrorKind::UnopenedSection("a".to_string()),
+            line: 1,
+            col: 1,
+            snippet: "{{/a}}".to_string(),
+        });
+
+        assert_eq!(compile_err("{{a}"), Error::Parse {
+            kind: ParseErrorKind::UnclosedTag,
+            line: 1,
+            col: 1,
+            snippet: "{{a}".to_string(),
+        });
+
+        assert_eq!(compile_err("{{{a}}x"), Error::Parse {
+            kind: ParseErrorKind::InvalidClosingTag,
+            line: 1,
+            col: 1,
+            snippet: "{{{a}}x".to_string(),
+        });
+
+        assert_eq!(compile_err("{{=<%=}}"), Error::Parse {
+            kind: ParseErrorKind::InvalidDelimiterTag,
+            line: 1,
+            col: 1,
+            snippet: "{{=<%=}}".to_string(),
+        });
+
+        match compile_err("{{#a}}") {
+            Error::Parse { kind: ParseErrorKind::UnclosedSection(name), .. } => {
+                assert_eq!(name, "a".to_string());
+            }
+            err => panic!("unexpected error {:?}", err),
+        }
+
+        // Missing partials are only an error in strict mode.
+        let (_, partials) = Compiler::new(context(), "{{>missing}}".chars()).compile().unwrap();
+        assert!(partials["missing"].is_empty());
+        match Compiler::new(context().with_strict(), "{{>missing}}".chars()).compile() {
+            Err(err) => assert_eq!(err, Error::PartialNotFound("missing".to_string())),
+            Ok(_) => panic!("expected a missing partial to fail to compile in strict mode"),
+        }
+    }
+
+    #[test]
+    fn test_check() {
+        let ctx = Context::new(Path::new("."));
+
+        assert_eq!(ctx.check("{{#a}}{{b}}{{/a}}".chars()), Vec::new());
+
+        let template = "{{#a}}\n{{#b}}{{ }}\n{{/a}}\n{{/c}}\n{{{d}} {{#e}}";
+        assert_eq!(ctx.check(template.chars()), vec!(
+            Diagnostic {
+                kind: ParseErrorKind::EmptyTag,
+                line: 2,
+                col: 7,
+                snippet: "{{ }}".to_string(),
+            },
+            Diagnostic {
+                kind: ParseErrorKind::UnclosedSection("b".to_string()),
+                line: 2,
+                col: 1,
+                snippet: "{{#b}}".to_string(),
+            },
+            Diagnostic {
+                kind: ParseErrorKind::UnopenedSection("c".to_string()),
+                line: 4,
+                col: 1,
+                snippet: "{{/c}}".to_string(),
+            },
+            Diagnostic {
+                kind: ParseErrorKind::InvalidClosingTag,
+                line: 5,
+                col: 1,
+                snippet: "{{{d}} ".to_string(),
+            },
+            Diagnostic {
+                kind: ParseErrorKind::UnclosedSection("e".to_string()),
+                line: 5,
+                col: 8,
+                snippet: "{{#e}}".to_string(),
+            }
+        ));
+    }
+
+    #[test]
+    fn test_compile_spans() {
+        let tokens = compile_str("a {{b}}\n{{#c}}{{{d}}}{{/c}}");
+        let spans: Vec<Span> = tokens.iter().map(|token| token.span()).collect();
+
+        assert_eq!(spans, vec!(
+            Span { start: 0, end: 2, line: 1, col: 1 },
+            Span { start: 2, end: 7, line: 1, col: 3 },
+            Span { start: 7, end: 8, line: 1, col: 8 },
+            Span { start: 8, end: 27, line: 2, col: 1 }
+        ));
+
+        match tokens[3] {
+            Section(_, _, ref children, _, _, _, _, _, _) => {
+                assert_eq!(children[0].span(), Span { start: 14, end: 21, line: 2, col: 7 });
+            }
+            _ => panic!(),
+        }
+    }
 }
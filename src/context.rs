@@ -1,11 +1,19 @@
 use std::borrow::ToOwned;
+use std::collections::HashMap;
 use std::{fmt, str};
 use std::fs::File;
 use std::io::Read;
 use std::path::{Path, PathBuf, AsPath};
+use std::rc::Rc;
 
-use compiler::Compiler;
-use error::Error;
+use ast;
+use compiler::{self, Compiler};
+use error::{Diagnostic, Error};
+use escape::{Escaper, Html, Xml, Json, Shell, Latex};
+use filter::{Filter, Upper, Lower, Trim, Truncate};
+use helper::{Helper, IfEq};
+use loader::{FileLoader, PartialLoader};
+use parser::Parser;
 use template::{self, Template};
 
 /// Represents the shared metadata needed to compile and render a mustache
@@ -14,6 +22,14 @@ use template::{self, Template};
 pub struct Context {
     pub template_path: PathBuf,
     pub template_extension: String,
+    loader: Option<Rc<Box<PartialLoader>>>,
+    escaper: Rc<Box<Escaper>>,
+    extension_escapers: HashMap<String, Rc<Box<Escaper>>>,
+    strict: bool,
+    filters: HashMap<String, Rc<Box<Filter>>>,
+    filters_enabled: bool,
+    helpers: HashMap<String, Rc<Box<Helper>>>,
+    helpers_enabled: bool,
 }
 
 impl fmt::Debug for Context {
@@ -30,15 +46,217 @@ impl Context {
         Context {
             template_path: path.as_path().to_owned(),
             template_extension: "mustache".to_string(),
+            loader: None,
+            escaper: Rc::new(Box::new(Html) as Box<Escaper>),
+            extension_escapers: HashMap::new(),
+            strict: false,
+            filters: HashMap::new(),
+            filters_enabled: false,
+            helpers: HashMap::new(),
+            helpers_enabled: false,
+        }
+        .register_filter("upper", Upper)
+        .register_filter("lower", Lower)
+        .register_filter("trim", Trim)
+        .register_filter("truncate", Truncate)
+        .register_helper("if_eq", IfEq)
+    }
+
+    /// Escapes the values of `{{name}}` tags with `escaper`, rather than
+    /// HTML escaping them.
+    pub fn with_escaper<E: Escaper + 'static>(self, escaper: E) -> Context {
+        Context {
+            escaper: Rc::new(Box::new(escaper) as Box<Escaper>),
+            .. self
+        }
+    }
+
+    /// Returns the `Escaper` used for `{{name}}` tags.
+    pub fn escaper(&self) -> &Escaper {
+        &**self.escaper
+    }
+
+    /// Escapes templates and partials named with `extension`, like
+    /// `page.html` for `html`, with `escaper`. The template extension
+    /// follows it, so `page.html` is read from `page.html.mustache`.
+    ///
+    /// An extension's escaper takes the place of the one set with
+    /// `with_escaper` for the templates and partials it applies to.
+    pub fn with_extension_escaper<E: Escaper + 'static>(
+        mut self,
+        extension: &str,
+        escaper: E
+    ) -> Context {
+        let escaper = Rc::new(Box::new(escaper) as Box<Escaper>);
+        self.extension_escapers.insert(extension.to_string(), escaper);
+        self
+    }
+
+    /// Escapes templates and partials by their extension with the built in
+    /// escapers: `html` and `htm` as HTML, `xml` as XML, `json` as JSON
+    /// strings, `sh` as shell words and `tex` as LaTeX. Without this, every
+    /// template uses the escaper set with `with_escaper`, whatever its name.
+    pub fn with_default_extension_escapers(self) -> Context {
+        self.with_extension_escaper("html", Html)
+            .with_extension_escaper("htm", Html)
+            .with_extension_escaper("xml", Xml)
+            .with_extension_escaper("json", Json)
+            .with_extension_escaper("sh", Shell)
+            .with_extension_escaper("tex", Latex)
+    }
+
+    /// Returns the `Escaper` for the template or partial `name`, if its
+    /// extension has one.
+    pub fn extension_escaper(&self, name: &str) -> Option<&Escaper> {
+        self.extension_escapers.get(extension(name)).map(|escaper| &**escaper)
+    }
+
+    /// Returns the context to compile the template `name` with, which uses
+    /// the escaper for its extension if it has one.
+    pub fn for_template(&self, name: &str) -> Context {
+        match self.extension_escapers.get(extension(name)) {
+            Some(escaper) => Context { escaper: escaper.clone(), .. self.clone() },
+            None => self.clone(),
+        }
+    }
+
+    /// Makes rendering fail with `Error::MissingVariable` when a variable or
+    /// section isn't found in the data, rather than rendering nothing. A
+    /// partial that can't be found is `Error::PartialNotFound`, when the
+    /// template is compiled for a static `{{>name}}`, or when it is rendered
+    /// for a dynamic `{{>*name}}`. An inverted section, `{{^name}}`, still
+    /// renders when `name` is missing. `Template::with_strict` turns this on
+    /// for a single template.
+    pub fn with_strict(self) -> Context {
+        Context {
+            strict: true,
+            .. self
+        }
+    }
+
+    /// Returns whether missing variables and sections are errors.
+    pub fn is_strict(&self) -> bool {
+        self.strict
+    }
+
+    /// Lets variables be piped through filters, as in
+    /// `{{ name | upper | truncate:20 }}`. The `upper`, `lower`, `trim` and
+    /// `truncate` filters are built in.
+    pub fn with_filters(self) -> Context {
+        Context {
+            filters_enabled: true,
+            .. self
+        }
+    }
+
+    /// Adds the filter `name`, and lets variables be piped through filters.
+    /// A filter with the same name as a built-in one replaces it.
+    pub fn with_filter<F: Filter + 'static>(self, name: &str, filter: F) -> Context {
+        self.register_filter(name, filter).with_filters()
+    }
+
+    /// Returns whether variables can be piped through filters.
+    pub fn has_filters(&self) -> bool {
+        self.filters_enabled
+    }
+
+    /// Returns the filter `name`, if there is one.
+    pub fn filter(&self, name: &str) -> Option<&Filter> {
+        self.filters.get(name).map(|filter| &**filter)
+    }
+
+    fn register_filter<F: Filter + 'static>(mut self, name: &str, filter: F) -> Context {
+        self.filters.insert(name.to_string(), Rc::new(Box::new(filter) as Box<Filter>));
+        self
+    }
+
+    /// Lets tags and sections call helpers with arguments, as in
+    /// `{{#if_eq status "active"}}`. The `if_eq` helper is built in.
+    pub fn with_helpers(self) -> Context {
+        Context {
+            helpers_enabled: true,
+            .. self
+        }
+    }
+
+    /// Adds the helper `name`, and lets tags and sections call helpers.
+    pub fn with_helper<H: Helper + 'static>(self, name: &str, helper: H) -> Context {
+        self.register_helper(name, helper).with_helpers()
+    }
+
+    /// Returns whether tags and sections can call helpers.
+    pub fn has_helpers(&self) -> bool {
+        self.helpers_enabled
+    }
+
+    /// Returns the helper `name`, if helpers are turned on and there is one.
+    pub fn helper(&self, name: &str) -> Option<&Helper> {
+        if !self.helpers_enabled {
+            return None;
+        }
+
+        self.helpers.get(name).map(|helper| &**helper)
+    }
+
+    fn register_helper<H: Helper + 'static>(mut self, name: &str, helper: H) -> Context {
+        self.helpers.insert(name.to_string(), Rc::new(Box::new(helper) as Box<Helper>));
+        self
+    }
+
+    /// Loads partials with `loader`, rather than from the files in the
+    /// template path.
+    pub fn with_loader<L: PartialLoader + 'static>(self, loader: L) -> Context {
+        Context {
+            loader: Some(Rc::new(Box::new(loader) as Box<PartialLoader>)),
+            .. self
         }
     }
 
     /// Compiles a template from a string
-    pub fn compile<IT: Iterator<Item=char>>(&self, reader: IT) -> Template {
+    pub fn compile<IT: Iterator<Item=char>>(&self, reader: IT) -> Result<Template, Error> {
         let compiler = Compiler::new(self.clone(), reader);
-        let (tokens, partials) = compiler.compile();
+        let (tokens, partials) = try!(compiler.compile());
 
-        template::new(self.clone(), tokens, partials)
+        Ok(template::new(self.clone(), Rc::new(tokens), partials))
+    }
+
+    /// Compiles a syntax tree into a template. The tree is usually one read
+    /// with `Template::ast` and then changed with a `VisitorMut`. Any
+    /// partials it uses are loaded just as they are for `compile`.
+    pub fn compile_ast(&self, nodes: Vec<ast::Node>) -> Result<Template, Error> {
+        let (tokens, partials) = try!(compiler::compile_ast(self.clone(), &nodes));
+
+        Ok(template::new(self.clone(), Rc::new(tokens), partials))
+    }
+
+    /// Checks a template for syntax errors without compiling it. Unlike
+    /// `compile`, this doesn't stop at the first error, but reports every
+    /// error found in the template.
+    pub fn check<IT: Iterator<Item=char>>(&self, mut reader: IT) -> Vec<Diagnostic> {
+        Parser::new(&mut reader, "{{", "}}")
+            .with_filters(self.filters_enabled)
+            .with_helpers(self.helpers_enabled)
+            .check()
+    }
+
+    /// Loads the source of the partial `name`.
+    pub fn load_partial(&self, name: &str) -> Result<String, Error> {
+        match self.loader {
+            Some(ref loader) => loader.load(name),
+            None => {
+                FileLoader::new(&self.template_path, &self.template_extension).load(name)
+            }
+        }
+    }
+
+    /// Returns when the partial `name` was last modified, if that is known.
+    pub fn partial_modified(&self, name: &str) -> Option<u64> {
+        match self.loader {
+            Some(ref loader) => loader.modified(name),
+            None => {
+                FileLoader::new(&self.template_path, &self.template_extension).modified(name)
+            }
+        }
     }
 
     /// Compiles a template from a path.
@@ -46,11 +264,39 @@ impl Context {
         // FIXME(#6164): This should use the file decoding tools when they are
         // written. For now we'll just read the file and treat it as UTF-8file.
         let mut path = self.template_path.join(path);
-        path.set_extension(&self.template_extension);
+
+        // Keep any extension the template has, like the `html` of
+        // `page.html`, since that picks its escaper.
+        let name = match path.file_name().and_then(|name| name.to_str()) {
+            Some(name) => name.to_string(),
+            None => String::new(),
+        };
+        let suffix = format!(".{}", self.template_extension);
+        let name = if name.ends_with(&suffix) {
+            name.slice_to(name.len() - suffix.len()).to_string()
+        } else {
+            path.set_file_name(&format!("{}{}", name, suffix));
+            name
+        };
+
         let mut file = try!(File::open(&path));
         let mut template = String::new();
         try!(file.read_to_string(&mut template));
 
-        Ok(self.compile(template.chars()))
+        self.for_template(&name).compile(template.chars())
+    }
+}
+
+/// Returns the extension of the template or partial `name`, or an empty
+/// string if it doesn't have one.
+fn extension(name: &str) -> &str {
+    let base = match name.rfind('/') {
+        Some(pos) => name.slice_from(pos + 1),
+        None => name,
+    };
+
+    match base.rfind('.') {
+        Some(pos) => base.slice_from(pos + 1),
+        None => "",
     }
 }
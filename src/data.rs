@@ -1,13 +1,22 @@
 use std::cell::RefCell;
-use std::collections::HashMap;
 use std::fmt;
+use serialize::json::{self, Json};
+
+use error::Error;
+use map::Map;
+use template::LambdaContext;
 
 pub enum Data {
     Str(String),
     Bool(bool),
+    Int(i64),
+    UInt(u64),
+    Float(f64),
+    Null,
     Vec(Vec<Data>),
-    Map(HashMap<String, Data>),
-    Fun(RefCell<Box<Fn(String) -> String>>),
+    Map(Map),
+    Fun(RefCell<Box<FnMut(String) -> String>>),
+    Lambda(RefCell<Box<FnMut(&mut LambdaContext) -> Result<String, Error>>>),
 }
 
 impl PartialEq for Data {
@@ -16,9 +25,14 @@ impl PartialEq for Data {
         match (self, other) {
             (&Data::Str(ref v0), &Data::Str(ref v1)) => v0 == v1,
             (&Data::Bool(ref v0), &Data::Bool(ref v1)) => v0 == v1,
+            (&Data::Int(ref v0), &Data::Int(ref v1)) => v0 == v1,
+            (&Data::UInt(ref v0), &Data::UInt(ref v1)) => v0 == v1,
+            (&Data::Float(ref v0), &Data::Float(ref v1)) => v0 == v1,
+            (&Data::Null, &Data::Null) => true,
             (&Data::Vec(ref v0), &Data::Vec(ref v1)) => v0 == v1,
             (&Data::Map(ref v0), &Data::Map(ref v1)) => v0 == v1,
-            (&Data::Fun(_), &Data::Fun(_)) => panic!("cannot compare closures"),
+            (&Data::Fun(_), &Data::Fun(_))
+            | (&Data::Lambda(_), &Data::Lambda(_)) => panic!("cannot compare closures"),
             (_, _) => false,
         }
     }
@@ -29,9 +43,148 @@ impl fmt::Debug for Data {
         match *self {
             Data::Str(ref v) => write!(f, "Str({})", v),
             Data::Bool(v) => write!(f, "Bool({})", v),
+            Data::Int(v) => write!(f, "Int({})", v),
+            Data::UInt(v) => write!(f, "UInt({})", v),
+            Data::Float(v) => write!(f, "Float({})", v),
+            Data::Null => write!(f, "Null"),
             Data::Vec(ref v) => write!(f, "Vec({:?})", v),
             Data::Map(ref v) => write!(f, "Map({:?})", v),
             Data::Fun(_) => write!(f, "Fun(...)"),
+            Data::Lambda(_) => write!(f, "Lambda(...)"),
+        }
+    }
+}
+
+impl Data {
+    /// Returns whether a section for the value is skipped. `false`, empty
+    /// strings, zero, `NaN`, null and empty lists are falsey, everything else
+    /// is truthy.
+    pub fn is_falsey(&self) -> bool {
+        match *self {
+            Data::Str(ref s) => s.is_empty(),
+            Data::Bool(v) => !v,
+            Data::Int(v) => v == 0,
+            Data::UInt(v) => v == 0,
+            Data::Float(v) => v == 0.0 || v.is_nan(),
+            Data::Null => true,
+            Data::Vec(ref v) => v.is_empty(),
+            _ => false,
         }
     }
+
+    /// Converts a JSON value to `Data`. Numbers keep their JSON type, so
+    /// `1` becomes `Data::UInt`, `-1` becomes `Data::Int` and `1.0` becomes
+    /// `Data::Float`.
+    ///
+    /// A `json::Object` is a `BTreeMap`, which sorts its keys, so the order
+    /// of the keys in the JSON source is already lost. The maps converted
+    /// from objects have their keys in sorted order.
+    pub fn from_json(json: &Json) -> Data {
+        match *json {
+            Json::I64(v) => Data::Int(v),
+            Json::U64(v) => Data::UInt(v),
+            Json::F64(v) => Data::Float(v),
+            Json::String(ref v) => Data::Str(v.clone()),
+            Json::Boolean(v) => Data::Bool(v),
+            Json::Array(ref v) => Data::Vec(v.iter().map(Data::from_json).collect()),
+            Json::Object(ref v) => {
+                Data::Map(v.iter().map(|(k, v)| (k.clone(), Data::from_json(v))).collect())
+            }
+            Json::Null => Data::Null,
+        }
+    }
+
+    /// Converts the data to a JSON value. Closures have no JSON equivalent,
+    /// so data containing one is `Error::UnsupportedType`. Since a
+    /// `json::Object` sorts its keys, maps lose their insertion order.
+    pub fn to_json(&self) -> Result<Json, Error> {
+        Ok(match *self {
+            Data::Str(ref v) => Json::String(v.clone()),
+            Data::Bool(v) => Json::Boolean(v),
+            Data::Int(v) => Json::I64(v),
+            Data::UInt(v) => Json::U64(v),
+            Data::Float(v) => Json::F64(v),
+            Data::Null => Json::Null,
+            Data::Vec(ref v) => {
+                let mut array = Vec::with_capacity(v.len());
+                for value in v.iter() {
+                    array.push(try!(value.to_json()));
+                }
+                Json::Array(array)
+            }
+            Data::Map(ref v) => {
+                let mut object = json::Object::new();
+                for (key, value) in v.iter() {
+                    object.insert(key.clone(), try!(value.to_json()));
+                }
+                Json::Object(object)
+            }
+            Data::Fun(_) | Data::Lambda(_) => { return Err(Error::UnsupportedType); }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use serialize::json::{self, Json};
+
+    use error::Error;
+    use map::Map;
+    use super::Data;
+
+    #[test]
+    fn test_from_json() {
+        let json = json::from_str(
+            "{\"a\": [1, -1, 1.5, \"x\", true, null], \"b\": {}}").unwrap();
+
+        let mut m = Map::new();
+        m.insert("a".to_string(), Data::Vec(vec!(
+            Data::UInt(1),
+            Data::Int(-1),
+            Data::Float(1.5),
+            Data::Str("x".to_string()),
+            Data::Bool(true),
+            Data::Null)));
+        m.insert("b".to_string(), Data::Map(Map::new()));
+
+        assert_eq!(Data::from_json(&json), Data::Map(m));
+    }
+
+    #[test]
+    fn test_json_sorts_keys() {
+        let json = json::from_str("{\"c\": 1, \"a\": 2, \"b\": 3}").unwrap();
+
+        match Data::from_json(&json) {
+            Data::Map(ref m) => {
+                let keys: Vec<&str> = m.keys().map(|key| key.as_slice()).collect();
+                assert_eq!(keys, vec!("a", "b", "c"));
+            }
+            value => panic!("unexpected value {:?}", value),
+        }
+
+        let mut m = Map::new();
+        m.insert("c".to_string(), Data::UInt(1));
+        m.insert("a".to_string(), Data::UInt(2));
+
+        match Data::Map(m).to_json() {
+            Ok(Json::Object(ref object)) => {
+                let keys: Vec<&str> = object.keys().map(|key| key.as_slice()).collect();
+                assert_eq!(keys, vec!("a", "c"));
+            }
+            value => panic!("unexpected value {:?}", value),
+        }
+    }
+
+    #[test]
+    fn test_to_json() {
+        let json = json::from_str(
+            "{\"a\": [1, -1, 1.5, \"x\", true, null], \"b\": {}}").unwrap();
+
+        assert_eq!(Data::from_json(&json).to_json(), Ok(json));
+
+        let f = Data::Fun(RefCell::new(Box::new(|s| s) as Box<FnMut(String) -> String>));
+        assert_eq!(Data::Vec(vec!(f)).to_json(), Err(Error::UnsupportedType));
+        assert_eq!(Data::Float(0.5).to_json(), Ok(Json::F64(0.5)));
+    }
 }
@@ -1,8 +1,8 @@
-use std::collections::HashMap;
 use serialize;
 
 use data::Data;
 use error::Error;
+use map::Map;
 
 pub struct Encoder {
     pub data: Vec<Data>,
@@ -12,30 +12,38 @@ impl Encoder {
     pub fn new() -> Encoder {
         Encoder { data: Vec::new() }
     }
+
+    /// Pushes a map from the variant `name` to its `value`.
+    fn emit_variant(&mut self, name: &str, value: Data) -> EncoderResult {
+        let mut m = Map::new();
+        m.insert(name.to_string(), value);
+        self.data.push(Data::Map(m));
+        Ok(())
+    }
 }
 
 pub type EncoderResult = Result<(), Error>;
 
 impl serialize::Encoder for Encoder {
     type Error = Error;
-    fn emit_nil(&mut self) -> EncoderResult { Err(Error::UnsupportedType) }
+    fn emit_nil(&mut self) -> EncoderResult { self.data.push(Data::Null); Ok(()) }
 
-    fn emit_uint(&mut self, v: usize) -> EncoderResult { self.emit_str(&v.to_string()) }
-    fn emit_u64(&mut self, v: u64) -> EncoderResult   { self.emit_str(&v.to_string()) }
-    fn emit_u32(&mut self, v: u32) -> EncoderResult   { self.emit_str(&v.to_string()) }
-    fn emit_u16(&mut self, v: u16) -> EncoderResult   { self.emit_str(&v.to_string()) }
-    fn emit_u8(&mut self, v: u8) -> EncoderResult     { self.emit_str(&v.to_string()) }
+    fn emit_uint(&mut self, v: usize) -> EncoderResult { self.emit_u64(v as u64) }
+    fn emit_u64(&mut self, v: u64) -> EncoderResult   { self.data.push(Data::UInt(v)); Ok(()) }
+    fn emit_u32(&mut self, v: u32) -> EncoderResult   { self.emit_u64(v as u64) }
+    fn emit_u16(&mut self, v: u16) -> EncoderResult   { self.emit_u64(v as u64) }
+    fn emit_u8(&mut self, v: u8) -> EncoderResult     { self.emit_u64(v as u64) }
 
-    fn emit_int(&mut self, v: isize) -> EncoderResult { self.emit_str(&v.to_string()) }
-    fn emit_i64(&mut self, v: i64) -> EncoderResult { self.emit_str(&v.to_string()) }
-    fn emit_i32(&mut self, v: i32) -> EncoderResult { self.emit_str(&v.to_string()) }
-    fn emit_i16(&mut self, v: i16) -> EncoderResult { self.emit_str(&v.to_string()) }
-    fn emit_i8(&mut self, v: i8) -> EncoderResult   { self.emit_str(&v.to_string()) }
+    fn emit_int(&mut self, v: isize) -> EncoderResult { self.emit_i64(v as i64) }
+    fn emit_i64(&mut self, v: i64) -> EncoderResult { self.data.push(Data::Int(v)); Ok(()) }
+    fn emit_i32(&mut self, v: i32) -> EncoderResult { self.emit_i64(v as i64) }
+    fn emit_i16(&mut self, v: i16) -> EncoderResult { self.emit_i64(v as i64) }
+    fn emit_i8(&mut self, v: i8) -> EncoderResult   { self.emit_i64(v as i64) }
 
     fn emit_bool(&mut self, v: bool) -> EncoderResult { self.data.push(Data::Bool(v)); Ok(()) }
 
-    fn emit_f64(&mut self, v: f64) -> EncoderResult { self.emit_str(&v.to_string()) }
-    fn emit_f32(&mut self, v: f32) -> EncoderResult { self.emit_str(&v.to_string()) }
+    fn emit_f64(&mut self, v: f64) -> EncoderResult { self.data.push(Data::Float(v)); Ok(()) }
+    fn emit_f32(&mut self, v: f32) -> EncoderResult { self.emit_f64(v as f64) }
 
     fn emit_char(&mut self, v: char) -> EncoderResult {
         let mut text = String::with_capacity(1);
@@ -45,44 +53,72 @@ impl serialize::Encoder for Encoder {
     }
     fn emit_str(&mut self, v: &str) -> EncoderResult { self.data.push(Data::Str(v.to_string())); Ok(()) }
 
-    fn emit_enum<F>(&mut self, _name: &str, _f: F) -> EncoderResult where F:FnOnce(&mut Self) -> EncoderResult {
-        Err(Error::UnsupportedType)
+    fn emit_enum<F>(&mut self, _name: &str, f: F) -> EncoderResult where F:FnOnce(&mut Self) -> EncoderResult {
+        f(self)
     }
 
+    // Variants without arguments are encoded as their name, so `{{status}}`
+    // renders it. The others are encoded as a map from their name to their
+    // argument, or to a list of their arguments if there are several. Either
+    // way, `{{#status.Active}}` renders only for that variant, since looking
+    // up a string's own value finds the string.
     fn emit_enum_variant<F>(&mut self,
-                         _name: &str,
+                         name: &str,
                          _id: usize,
-                         _len: usize,
-                         _f: F) -> EncoderResult where F:FnOnce(&mut Self) -> EncoderResult {
-        Err(Error::UnsupportedType)
+                         len: usize,
+                         f: F) -> EncoderResult where F:FnOnce(&mut Self) -> EncoderResult {
+        if len == 0 {
+            return self.emit_str(name);
+        }
+
+        try!(self.emit_seq(len, f));
+        let value = match self.data.pop() {
+            Some(Data::Vec(mut v)) => {
+                if len == 1 {
+                    match v.pop() {
+                        Some(value) => value,
+                        None => { return Err(Error::MissingElements); }
+                    }
+                } else {
+                    Data::Vec(v)
+                }
+            }
+            _ => { return Err(Error::UnsupportedType); }
+        };
+        self.emit_variant(name, value)
     }
 
     fn emit_enum_variant_arg<F>(&mut self,
-                             _a_idx: usize,
-                             _f: F) -> EncoderResult where F:FnOnce(&mut Self) -> EncoderResult {
-        Err(Error::UnsupportedType)
+                             a_idx: usize,
+                             f: F) -> EncoderResult where F:FnOnce(&mut Self) -> EncoderResult {
+        self.emit_seq_elt(a_idx, f)
     }
 
     fn emit_enum_struct_variant<F>(&mut self,
-                                _v_name: &str,
+                                v_name: &str,
                                 _v_id: usize,
-                                _len: usize,
-                                _f: F) -> EncoderResult where F:FnOnce(&mut Self) -> EncoderResult {
-        Err(Error::UnsupportedType)
+                                len: usize,
+                                f: F) -> EncoderResult where F:FnOnce(&mut Self) -> EncoderResult {
+        try!(self.emit_struct(v_name, len, f));
+        let value = match self.data.pop() {
+            Some(value) => value,
+            None => { return Err(Error::MissingElements); }
+        };
+        self.emit_variant(v_name, value)
     }
 
     fn emit_enum_struct_variant_field<F>(&mut self,
-                                      _f_name: &str,
-                                      _f_idx: usize,
-                                      _f: F) -> EncoderResult where F:FnOnce(&mut Self) -> EncoderResult {
-        Err(Error::UnsupportedType)
+                                      f_name: &str,
+                                      f_idx: usize,
+                                      f: F) -> EncoderResult where F:FnOnce(&mut Self) -> EncoderResult {
+        self.emit_struct_field(f_name, f_idx, f)
     }
 
     fn emit_struct<F>(&mut self,
                    _name: &str,
                    _len: usize,
                    f: F) -> EncoderResult where F:FnOnce(&mut Self) -> EncoderResult {
-        self.data.push(Data::Map(HashMap::new()));
+        self.data.push(Data::Map(Map::new()));
         f(self)
     }
 
@@ -126,16 +162,17 @@ impl serialize::Encoder for Encoder {
     }
 
     // Specialized types:
-    fn emit_option<F>(&mut self, _f: F) -> EncoderResult where F:FnOnce(&mut Self) -> EncoderResult {
-        Err(Error::UnsupportedType)
+    fn emit_option<F>(&mut self, f: F) -> EncoderResult where F:FnOnce(&mut Self) -> EncoderResult {
+        f(self)
     }
 
+    // `None` is null, which is falsey, and `Some` is just its value.
     fn emit_option_none(&mut self) -> EncoderResult {
-        Err(Error::UnsupportedType)
+        self.emit_nil()
     }
 
-    fn emit_option_some<F>(&mut self, _f: F) -> EncoderResult where F:FnOnce(&mut Self) -> EncoderResult {
-        Err(Error::UnsupportedType)
+    fn emit_option_some<F>(&mut self, f: F) -> EncoderResult where F:FnOnce(&mut Self) -> EncoderResult {
+        f(self)
     }
 
     fn emit_seq<F>(&mut self, _len: usize, f: F) -> EncoderResult where F:FnOnce(&mut Self) -> EncoderResult {
@@ -159,20 +196,23 @@ impl serialize::Encoder for Encoder {
     }
 
     fn emit_map<F>(&mut self, _len: usize, f: F) -> EncoderResult where F:FnOnce(&mut Self) -> EncoderResult {
-        self.data.push(Data::Map(HashMap::new()));
+        self.data.push(Data::Map(Map::new()));
         f(self)
     }
 
     fn emit_map_elt_key<F>(&mut self, _idx: usize, f: F) -> EncoderResult where F:FnOnce(&mut Self) -> EncoderResult {
         try!(f(self));
-        let last = match self.data.last() {
-            Some(d) => d,
+        let key = match self.data.pop() {
+            Some(Data::Str(s)) => s,
+            // Integer keys are used as strings, as they were before numbers
+            // had a `Data` variant of their own.
+            Some(Data::Int(v)) => v.to_string(),
+            Some(Data::UInt(v)) => v.to_string(),
+            Some(_) => { return Err(Error::KeyIsNotString); }
             None => { return Err(Error::MissingElements); }
         };
-        match *last {
-            Data::Str(_) => Ok(()),
-            _ => Err(Error::KeyIsNotString),
-        }
+        self.data.push(Data::Str(key));
+        Ok(())
     }
 
     fn emit_map_elt_val<F>(&mut self, _idx: usize, f: F) -> EncoderResult where F:FnOnce(&mut Self) -> EncoderResult {
@@ -204,3 +244,90 @@ pub fn encode<'a, T: serialize::Encodable>(data: &T) -> Result<Data, Error> {
         None => panic!("Error: Nothing to pop!"),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use data::Data;
+    use map::Map;
+    use super::encode;
+    use super::super::compile_str;
+
+    #[derive(Encodable)]
+    enum Status {
+        Pending,
+        Active(u32),
+        Moved(String, String),
+        Closed { reason: String },
+    }
+
+    #[derive(Encodable)]
+    struct Account {
+        name: Option<String>,
+        status: Status,
+    }
+
+    #[test]
+    fn test_encode_option() {
+        assert_eq!(encode(&Some(1u32)), Ok(Data::UInt(1)));
+        assert_eq!(encode(&None::<u32>), Ok(Data::Null));
+    }
+
+    #[test]
+    fn test_encode_enum() {
+        assert_eq!(encode(&Status::Pending), Ok(Data::Str("Pending".to_string())));
+
+        let mut m = Map::new();
+        m.insert("Active".to_string(), Data::UInt(3));
+        assert_eq!(encode(&Status::Active(3)), Ok(Data::Map(m)));
+
+        let mut m = Map::new();
+        m.insert("Moved".to_string(), Data::Vec(vec!(
+            Data::Str("a".to_string()),
+            Data::Str("b".to_string()))));
+        assert_eq!(encode(&Status::Moved("a".to_string(), "b".to_string())), Ok(Data::Map(m)));
+
+        let mut closed = Map::new();
+        closed.insert("reason".to_string(), Data::Str("spam".to_string()));
+        let mut m = Map::new();
+        m.insert("Closed".to_string(), Data::Map(closed));
+        assert_eq!(encode(&Status::Closed { reason: "spam".to_string() }), Ok(Data::Map(m)));
+    }
+
+    #[test]
+    fn test_encode_field_order() {
+        let account = Account { name: None, status: Status::Pending };
+
+        match encode(&account) {
+            Ok(Data::Map(m)) => {
+                let keys: Vec<&str> = m.keys().map(|key| key.as_slice()).collect();
+                assert_eq!(keys, vec!("name", "status"));
+            }
+            value => panic!("unexpected value {:?}", value),
+        }
+    }
+
+    #[test]
+    fn test_render_option_and_enum() {
+        let template = compile_str(
+            "{{#name}}{{name}}{{/name}}{{^name}}anonymous{{/name}}: \
+             {{#status.Pending}}pending{{/status.Pending}}\
+             {{#status.Active}}active for {{.}} days{{/status.Active}}\
+             {{#status.Closed}}closed for {{reason}}{{/status.Closed}}").unwrap();
+
+        let account = Account { name: None, status: Status::Pending };
+        assert_eq!(template.render_to_string(&account), Ok("anonymous: pending".to_string()));
+
+        let account = Account { name: Some("a".to_string()), status: Status::Active(3) };
+        assert_eq!(template.render_to_string(&account), Ok("a: active for 3 days".to_string()));
+
+        let account = Account {
+            name: Some("a".to_string()),
+            status: Status::Closed { reason: "spam".to_string() },
+        };
+        assert_eq!(template.render_to_string(&account), Ok("a: closed for spam".to_string()));
+
+        let template = compile_str("{{status}}").unwrap();
+        let account = Account { name: None, status: Status::Pending };
+        assert_eq!(template.render_to_string(&account), Ok("Pending".to_string()));
+    }
+}
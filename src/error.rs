@@ -2,13 +2,93 @@ use std::io;
 use std::error;
 use std::fmt;
 
-#[derive(PartialEq)]
+use parser::Span;
+
+#[derive(Debug, PartialEq)]
 pub enum Error {
     UnsupportedType,
     InvalidStr,
     MissingElements,
     KeyIsNotString,
     IoError(io::Error),
+    FmtError(fmt::Error),
+    PartialNotFound(String),
+    InvalidPartialName(String),
+    TemplateNotFound(String),
+    RecursiveLambda,
+    FilterNotFound(String),
+    InvalidFilter(String),
+    HelperNotFound(String),
+    InvalidHelper(String),
+    MissingVariable {
+        path: String,
+        span: Span,
+    },
+    Parse {
+        kind: ParseErrorKind,
+        line: usize,
+        col: usize,
+        snippet: String,
+    },
+}
+
+/// The different ways a template can fail to parse.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ParseErrorKind {
+    UnclosedTag,
+    EmptyTag,
+    /// A section that is never closed. It is reported at its opening tag,
+    /// since that is the tag that needs fixing.
+    UnclosedSection(String),
+    UnopenedSection(String),
+    UnbalancedUnescapeTag,
+    InvalidDelimiterTag,
+    InvalidClosingTag,
+    EmptyFilter,
+    InvalidArgument,
+}
+
+impl ParseErrorKind {
+    pub fn description(&self) -> &str {
+        match *self {
+            ParseErrorKind::UnclosedTag => "unclosed tag",
+            ParseErrorKind::EmptyTag => "empty tag",
+            ParseErrorKind::UnclosedSection(_) => "unclosed section",
+            ParseErrorKind::UnopenedSection(_) => "closing unopened section",
+            ParseErrorKind::UnbalancedUnescapeTag => "unbalanced \"{\" in tag",
+            ParseErrorKind::InvalidDelimiterTag => "invalid change delimiter tag content",
+            ParseErrorKind::InvalidClosingTag => "invalid closing tag delimiter",
+            ParseErrorKind::EmptyFilter => "empty filter",
+            ParseErrorKind::InvalidArgument => "invalid helper argument",
+        }
+    }
+}
+
+/// A syntax error found in a template, along with where it happened.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Diagnostic {
+    pub kind: ParseErrorKind,
+    pub line: usize,
+    pub col: usize,
+    pub snippet: String,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} at line {}, column {}: {}", self.kind, self.line, self.col, self.snippet)
+    }
+}
+
+impl fmt::Display for ParseErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ParseErrorKind::UnclosedSection(ref name)
+            | ParseErrorKind::UnopenedSection(ref name) => {
+                write!(f, "{} \"{}\"", self.description(), name)
+            }
+            _ => self.description().fmt(f),
+        }
+    }
 }
 
 impl error::Error for Error {
@@ -19,6 +99,17 @@ impl error::Error for Error {
             Error::MissingElements => "no elements in value",
             Error::KeyIsNotString => "key is not a string",
             Error::IoError(ref err) => err.description(),
+            Error::FmtError(_) => "formatting error",
+            Error::PartialNotFound(_) => "partial not found",
+            Error::InvalidPartialName(_) => "invalid partial name",
+            Error::TemplateNotFound(_) => "template not found",
+            Error::RecursiveLambda => "lambda called while it was already running",
+            Error::FilterNotFound(_) => "filter not found",
+            Error::InvalidFilter(_) => "invalid filter",
+            Error::HelperNotFound(_) => "helper not found",
+            Error::InvalidHelper(_) => "invalid helper call",
+            Error::MissingVariable { .. } => "missing variable",
+            Error::Parse { ref kind, .. } => kind.description(),
         }
     }
 
@@ -36,10 +127,41 @@ impl error::FromError<io::Error> for Error {
     }
 }
 
+impl error::FromError<fmt::Error> for Error {
+    fn from_error(err: fmt::Error) -> Error {
+        Error::FmtError(err)
+    }
+}
+
+impl error::FromError<Diagnostic> for Error {
+    fn from_error(diagnostic: Diagnostic) -> Error {
+        let Diagnostic { kind, line, col, snippet } = diagnostic;
+        Error::Parse {
+            kind: kind,
+            line: line,
+            col: col,
+            snippet: snippet,
+        }
+    }
+}
+
 impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match *self {
             Error::IoError(ref err) => err.fmt(f),
+            Error::PartialNotFound(ref name) => write!(f, "partial \"{}\" not found", name),
+            Error::InvalidPartialName(ref name) => write!(f, "invalid partial name \"{}\"", name),
+            Error::TemplateNotFound(ref name) => write!(f, "template \"{}\" not found", name),
+            Error::FilterNotFound(ref name) => write!(f, "filter \"{}\" not found", name),
+            Error::InvalidFilter(ref msg) => msg.fmt(f),
+            Error::HelperNotFound(ref name) => write!(f, "helper \"{}\" not found", name),
+            Error::InvalidHelper(ref msg) => msg.fmt(f),
+            Error::MissingVariable { ref path, span } => {
+                write!(f, "missing variable \"{}\" at line {}, column {}", path, span.line, span.col)
+            }
+            Error::Parse { ref kind, line, col, ref snippet } => {
+                write!(f, "{} at line {}, column {}: {}", kind, line, col, snippet)
+            }
             _ => error::Error::description(self).fmt(f),
         }
     }
@@ -0,0 +1,235 @@
+//! Ways of escaping the values of `{{name}}` tags.
+//!
+//! Templates are HTML escaped by default. A different `Escaper` can be set
+//! with `Context::with_escaper`, either one of the ones here or your own.
+
+use std::fmt;
+
+/// `Escaper` escapes the values of `{{name}}` tags as they are rendered. A
+/// value may be escaped in several pieces, so escapers shouldn't assume they
+/// are given all of it at once. Anything that wraps the whole value, like
+/// quotes, is written by `begin` and `end` instead.
+pub trait Escaper {
+    /// Writes `s` to `wr`, escaped.
+    fn escape(&self, s: &str, wr: &mut fmt::Write) -> fmt::Result;
+
+    /// Writes anything that goes before a value, even an empty one.
+    fn begin(&self, _wr: &mut fmt::Write) -> fmt::Result {
+        Ok(())
+    }
+
+    /// Writes anything that goes after a value, even an empty one.
+    fn end(&self, _wr: &mut fmt::Write) -> fmt::Result {
+        Ok(())
+    }
+}
+
+/// Escapes the characters that are special in HTML.
+#[derive(Clone, Copy, Debug)]
+pub struct Html;
+
+/// Escapes the characters that are special in XML.
+#[derive(Clone, Copy, Debug)]
+pub struct Xml;
+
+/// Escapes values for use inside a JSON string.
+#[derive(Clone, Copy, Debug)]
+pub struct Json;
+
+/// Quotes values so the shell treats them as a single word.
+#[derive(Clone, Copy, Debug)]
+pub struct Shell;
+
+/// Escapes the characters that are special in LaTeX.
+#[derive(Clone, Copy, Debug)]
+pub struct Latex;
+
+/// Doesn't escape anything, so `{{name}}` behaves like `{{{name}}}`.
+#[derive(Clone, Copy, Debug)]
+pub struct NoEscape;
+
+/// Writes `s` to `wr`, replacing the characters that `f` has a replacement
+/// for.
+fn replace<F>(s: &str, wr: &mut fmt::Write, f: F) -> fmt::Result
+    where F: Fn(char) -> Option<&'static str>
+{
+    let mut pos = 0;
+
+    for (i, c) in s.char_indices() {
+        if let Some(replacement) = f(c) {
+            try!(wr.write_str(s.slice(pos, i)));
+            try!(wr.write_str(replacement));
+            pos = i + c.len_utf8();
+        }
+    }
+
+    wr.write_str(s.slice_from(pos))
+}
+
+impl Escaper for Html {
+    fn escape(&self, s: &str, wr: &mut fmt::Write) -> fmt::Result {
+        replace(s, wr, |c| {
+            match c {
+                '<'  => Some("&lt;"),
+                '>'  => Some("&gt;"),
+                '&'  => Some("&amp;"),
+                '"'  => Some("&quot;"),
+                '\'' => Some("&#39;"),
+                _    => None,
+            }
+        })
+    }
+}
+
+impl Escaper for Xml {
+    fn escape(&self, s: &str, wr: &mut fmt::Write) -> fmt::Result {
+        replace(s, wr, |c| {
+            match c {
+                '<'  => Some("&lt;"),
+                '>'  => Some("&gt;"),
+                '&'  => Some("&amp;"),
+                '"'  => Some("&quot;"),
+                '\'' => Some("&apos;"),
+                _    => None,
+            }
+        })
+    }
+}
+
+impl Escaper for Json {
+    fn escape(&self, s: &str, wr: &mut fmt::Write) -> fmt::Result {
+        let mut pos = 0;
+
+        for (i, c) in s.char_indices() {
+            if c != '"' && c != '\\' && c >= ' ' {
+                continue;
+            }
+
+            try!(wr.write_str(s.slice(pos, i)));
+            try!(match c {
+                '"'    => wr.write_str("\\\""),
+                '\\'   => wr.write_str("\\\\"),
+                '\n'   => wr.write_str("\\n"),
+                '\r'   => wr.write_str("\\r"),
+                '\t'   => wr.write_str("\\t"),
+                '\x08' => wr.write_str("\\b"),
+                '\x0c' => wr.write_str("\\f"),
+                c      => write!(wr, "\\u{:04x}", c as u32),
+            });
+            pos = i + 1;
+        }
+
+        wr.write_str(s.slice_from(pos))
+    }
+}
+
+impl Escaper for Shell {
+    fn escape(&self, s: &str, wr: &mut fmt::Write) -> fmt::Result {
+        // Nothing is special inside single quotes, except for the quotes
+        // themselves, which have to be closed, escaped and reopened.
+        replace(s, wr, |c| if c == '\'' { Some("'\\''") } else { None })
+    }
+
+    // The quotes go around the whole value, so an empty value is still a
+    // word of its own.
+    fn begin(&self, wr: &mut fmt::Write) -> fmt::Result {
+        wr.write_str("'")
+    }
+
+    fn end(&self, wr: &mut fmt::Write) -> fmt::Result {
+        wr.write_str("'")
+    }
+}
+
+impl Escaper for Latex {
+    fn escape(&self, s: &str, wr: &mut fmt::Write) -> fmt::Result {
+        replace(s, wr, |c| {
+            match c {
+                '\\' => Some("\\textbackslash{}"),
+                '~'  => Some("\\textasciitilde{}"),
+                '^'  => Some("\\textasciicircum{}"),
+                '{'  => Some("\\{"),
+                '}'  => Some("\\}"),
+                '$'  => Some("\\$"),
+                '&'  => Some("\\&"),
+                '#'  => Some("\\#"),
+                '_'  => Some("\\_"),
+                '%'  => Some("\\%"),
+                _    => None,
+            }
+        })
+    }
+}
+
+impl Escaper for NoEscape {
+    fn escape(&self, s: &str, wr: &mut fmt::Write) -> fmt::Result {
+        wr.write_str(s)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use context::Context;
+    use data::Data;
+    use map::Map;
+    use super::{Escaper, Html, Xml, Json, Shell, Latex, NoEscape};
+
+    fn escape<E: Escaper>(escaper: E, pieces: &[&str]) -> String {
+        let mut escaped = String::new();
+        escaper.begin(&mut escaped).unwrap();
+        for piece in pieces.iter() {
+            escaper.escape(piece, &mut escaped).unwrap();
+        }
+        escaper.end(&mut escaped).unwrap();
+        escaped
+    }
+
+    #[test]
+    fn test_escapers() {
+        assert_eq!(escape(Html, &["<a href='x'>\"&\"</a>"]),
+                   "&lt;a href=&#39;x&#39;&gt;&quot;&amp;&quot;&lt;/a&gt;".to_string());
+        assert_eq!(escape(Xml, &["<a b='c'/>"]), "&lt;a b=&apos;c&apos;/&gt;".to_string());
+        assert_eq!(escape(Json, &["\"a\\b\"\n\u{1}é"]), "\\\"a\\\\b\\\"\\n\\u0001é".to_string());
+        assert_eq!(escape(Shell, &["it's"]), "'it'\\''s'".to_string());
+        assert_eq!(escape(Latex, &["50% of $x_1$"]), "50\\% of \\$x\\_1\\$".to_string());
+        assert_eq!(escape(NoEscape, &["<&>"]), "<&>".to_string());
+    }
+
+    #[test]
+    fn test_shell_escaper() {
+        // A value is quoted once, however many pieces it is written in.
+        assert_eq!(escape(Shell, &["a b", "it's", ""]), "'a bit'\\''s'".to_string());
+        assert_eq!(escape(Shell, &[]), "''".to_string());
+        assert_eq!(escape(Shell, &[""]), "''".to_string());
+
+        let template = Context::new(Path::new("."))
+            .with_escaper(Shell)
+            .compile("echo {{name}} {{empty}} {{missing}}".chars())
+            .unwrap();
+
+        let mut ctx = Map::new();
+        ctx.insert("name".to_string(), Data::Str("it's".to_string()));
+        ctx.insert("empty".to_string(), Data::Str("".to_string()));
+
+        assert_eq!(
+            template.render_data_to_string(&Data::Map(ctx)),
+            Ok("echo 'it'\\''s' '' ''".to_string()));
+    }
+
+    #[test]
+    fn test_context_escaper() {
+        let template = Context::new(Path::new("."))
+            .with_escaper(Json)
+            .compile("{\"name\": \"{{name}}\", \"raw\": \"{{{name}}}\"}".chars())
+            .unwrap();
+
+        let mut ctx = Map::new();
+        ctx.insert("name".to_string(), Data::Str("say \"hi\"".to_string()));
+
+        assert_eq!(
+            template.render_data_to_string(&Data::Map(ctx)),
+            Ok("{\"name\": \"say \\\"hi\\\"\", \"raw\": \"say \"hi\"\"}".to_string()));
+    }
+}
@@ -0,0 +1,129 @@
+//! Filters that transform the values of variables before they are rendered.
+//!
+//! Once filters are turned on with `Context::with_filters`, a variable can be
+//! piped through them, as in `{{ name | upper | truncate:20 }}`. Arguments
+//! follow the filter's name, separated by colons, and can be quoted to
+//! contain a colon or a pipe, as in `truncate:20:" | ..."`. Your own filters
+//! can be added with `Context::with_filter`.
+
+use data::Data;
+use error::Error;
+
+/// `Filter` transforms the value of a variable. The value it returns is
+/// given to the next filter, or rendered, and escaped, if there are no more.
+pub trait Filter {
+    /// Returns `value` transformed, using the arguments given in the tag.
+    fn filter(&self, value: &Data, args: &[String]) -> Result<Data, Error>;
+}
+
+impl<F> Filter for F where F: Fn(&Data, &[String]) -> Result<Data, Error> {
+    fn filter(&self, value: &Data, args: &[String]) -> Result<Data, Error> {
+        (*self)(value, args)
+    }
+}
+
+/// Converts the value to upper case.
+#[derive(Clone, Copy, Debug)]
+pub struct Upper;
+
+/// Converts the value to lower case.
+#[derive(Clone, Copy, Debug)]
+pub struct Lower;
+
+/// Removes the whitespace around the value.
+#[derive(Clone, Copy, Debug)]
+pub struct Trim;
+
+/// Shortens the value to at most the number of characters in its first
+/// argument. If a second argument is given, like the `...` of
+/// `truncate:20:...`, it is added to the end of values that were shortened.
+#[derive(Clone, Copy, Debug)]
+pub struct Truncate;
+
+/// Returns the text of a value, as it would be rendered.
+fn text(value: &Data) -> Result<String, Error> {
+    match *value {
+        Data::Str(ref s) => Ok(s.clone()),
+        Data::Bool(b) => Ok(b.to_string()),
+        Data::Int(v) => Ok(v.to_string()),
+        Data::UInt(v) => Ok(v.to_string()),
+        Data::Float(v) => Ok(v.to_string()),
+        Data::Null => Ok(String::new()),
+        _ => Err(Error::UnsupportedType),
+    }
+}
+
+impl Filter for Upper {
+    fn filter(&self, value: &Data, _args: &[String]) -> Result<Data, Error> {
+        Ok(Data::Str(try!(text(value)).chars().map(|c| c.to_uppercase()).collect()))
+    }
+}
+
+impl Filter for Lower {
+    fn filter(&self, value: &Data, _args: &[String]) -> Result<Data, Error> {
+        Ok(Data::Str(try!(text(value)).chars().map(|c| c.to_lowercase()).collect()))
+    }
+}
+
+impl Filter for Trim {
+    fn filter(&self, value: &Data, _args: &[String]) -> Result<Data, Error> {
+        Ok(Data::Str(StrExt::trim(&try!(text(value))).to_string()))
+    }
+}
+
+impl Filter for Truncate {
+    fn filter(&self, value: &Data, args: &[String]) -> Result<Data, Error> {
+        let len = match args.first().and_then(|arg| arg.parse::<usize>().ok()) {
+            Some(len) => len,
+            None => {
+                return Err(Error::InvalidFilter(
+                    "truncate expects a length, like `truncate:20`".to_string()));
+            }
+        };
+
+        let text = try!(text(value));
+        if text.chars().count() <= len {
+            return Ok(Data::Str(text));
+        }
+
+        let mut truncated: String = text.chars().take(len).collect();
+        if let Some(suffix) = args.get(1) {
+            truncated.push_str(suffix);
+        }
+        Ok(Data::Str(truncated))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use data::Data;
+    use error::Error;
+    use super::{Filter, Upper, Lower, Trim, Truncate};
+
+    fn apply<F: Filter>(filter: F, value: &str, args: &[&str]) -> Result<Data, Error> {
+        let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+        filter.filter(&Data::Str(value.to_string()), &args)
+    }
+
+    fn s(value: &str) -> Result<Data, Error> {
+        Ok(Data::Str(value.to_string()))
+    }
+
+    #[test]
+    fn test_filters() {
+        assert_eq!(apply(Upper, "héllo", &[]), s("HÉLLO"));
+        assert_eq!(apply(Lower, "ÀB", &[]), s("àb"));
+        assert_eq!(apply(Trim, " a b \n", &[]), s("a b"));
+        assert_eq!(Upper.filter(&Data::UInt(1), &[]), s("1"));
+        assert!(Upper.filter(&Data::Vec(Vec::new()), &[]).is_err());
+    }
+
+    #[test]
+    fn test_truncate() {
+        assert_eq!(apply(Truncate, "héllo", &["2"]), s("hé"));
+        assert_eq!(apply(Truncate, "héllo", &["2", "..."]), s("hé..."));
+        assert_eq!(apply(Truncate, "héllo", &["5", "..."]), s("héllo"));
+        assert!(apply(Truncate, "héllo", &[]).is_err());
+        assert!(apply(Truncate, "héllo", &["a"]).is_err());
+    }
+}
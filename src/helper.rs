@@ -0,0 +1,142 @@
+//! Helpers that templates call with arguments.
+//!
+//! Once helpers are turned on with `Context::with_helpers`, a tag or section
+//! can call a helper by name, followed by its arguments, in the style of
+//! Handlebars: `{{format_money amount currency}}`, or
+//! `{{#if_eq status "active"}}...{{/if_eq}}`. Each argument is either a
+//! quoted string, a whole number or the name of a value in the data. Your
+//! own helpers can be added with `Context::with_helper`.
+
+use data::Data;
+use error::Error;
+use template::LambdaContext;
+
+/// `Helper` is called by name from a template, with the values of its
+/// arguments. What it returns is rendered in place of the tag or section,
+/// and is escaped in a `{{name}}` tag. For a section, the `LambdaContext`
+/// has its source, which the helper can render if it wants to.
+pub trait Helper {
+    /// Calls the helper with the values of its arguments.
+    fn call(&self, args: &[&Data], ctx: &mut LambdaContext) -> Result<String, Error>;
+}
+
+impl<F> Helper for F where F: Fn(&[&Data], &mut LambdaContext) -> Result<String, Error> {
+    fn call(&self, args: &[&Data], ctx: &mut LambdaContext) -> Result<String, Error> {
+        (*self)(args, ctx)
+    }
+}
+
+/// Renders its section if its two arguments are equal.
+#[derive(Clone, Copy, Debug)]
+pub struct IfEq;
+
+/// Returns whether two values are equal, treating numbers with the same
+/// value as equal whether or not they are signed.
+fn equal(a: &Data, b: &Data) -> bool {
+    match (a, b) {
+        (&Data::Int(a), &Data::UInt(b)) | (&Data::UInt(b), &Data::Int(a)) => {
+            a >= 0 && a as u64 == b
+        }
+        _ => a == b,
+    }
+}
+
+impl Helper for IfEq {
+    fn call(&self, args: &[&Data], ctx: &mut LambdaContext) -> Result<String, Error> {
+        if args.len() != 2 {
+            return Err(Error::InvalidHelper("if_eq expects two arguments".to_string()));
+        }
+
+        if equal(args[0], args[1]) {
+            let src = ctx.source().to_string();
+            ctx.render(&src)
+        } else {
+            Ok(String::new())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use builder::MapBuilder;
+    use context::Context;
+    use data::Data;
+    use error::{Error, ParseErrorKind};
+    use template::LambdaContext;
+
+    fn format_money(args: &[&Data], _: &mut LambdaContext) -> Result<String, Error> {
+        match (args.get(0), args.get(1)) {
+            (Some(&&Data::UInt(cents)), Some(&&Data::Str(ref currency))) => {
+                Ok(format!("{}.{:02} {}", cents / 100, cents % 100, currency))
+            }
+            _ => {
+                let msg = "format_money expects an amount and a currency";
+                Err(Error::InvalidHelper(msg.to_string()))
+            }
+        }
+    }
+
+    fn render(ctx: &Context, template: &str) -> Result<String, Error> {
+        let data = MapBuilder::new()
+            .insert_uint("amount".to_string(), 1250)
+            .insert_str("currency".to_string(), "USD".to_string())
+            .insert_str("status".to_string(), "active".to_string())
+            .build();
+
+        try!(ctx.compile(template.chars())).render_data_to_string(&data)
+    }
+
+    #[test]
+    fn test_helpers() {
+        let ctx = Context::new(Path::new(".")).with_helper("format_money", format_money);
+
+        assert_eq!(
+            render(&ctx, "{{format_money amount currency}}, {{format_money amount \"<EUR>\"}}"),
+            Ok("12.50 USD, 12.50 &lt;EUR&gt;".to_string()));
+        assert_eq!(
+            render(&ctx, "{{{format_money amount \"<EUR>\"}}}"),
+            Ok("12.50 <EUR>".to_string()));
+
+        match render(&ctx, "{{format_money currency}}") {
+            Err(Error::InvalidHelper(_)) => { }
+            other => panic!("unexpected result {:?}", other),
+        }
+        assert_eq!(
+            render(&ctx, "{{shout status}}"),
+            Err(Error::HelperNotFound("shout".to_string())));
+
+        match ctx.compile("{{format_money \"USD}}".chars()) {
+            Err(Error::Parse { kind: ParseErrorKind::InvalidArgument, .. }) => { }
+            other => panic!("unexpected result {:?}", other.err()),
+        }
+    }
+
+    #[test]
+    fn test_if_eq() {
+        let ctx = Context::new(Path::new(".")).with_helpers();
+
+        assert_eq!(
+            render(&ctx, "{{#if_eq status \"active\"}}{{status}}{{/if_eq}}|\
+                          {{#if_eq status \"done\"}}done{{/if_eq}}|\
+                          {{#if_eq amount 1250}}1250{{/if_eq}}|\
+                          {{#if_eq missing \"a\"}}missing{{/if_eq}}"),
+            Ok("active||1250|".to_string()));
+
+        assert_eq!(
+            render(&ctx, "{{#if_eq status}}x{{/if_eq}}"),
+            Err(Error::InvalidHelper("if_eq expects two arguments".to_string())));
+
+        // Inverted sections can't call helpers.
+        assert_eq!(render(&ctx, "{{^status}}x{{/status}}"), Ok("".to_string()));
+        match ctx.compile("{{^if_eq status \"done\"}}x{{/if_eq}}".chars()) {
+            Err(Error::Parse { kind: ParseErrorKind::InvalidArgument, .. }) => { }
+            other => panic!("unexpected result {:?}", other.err()),
+        }
+
+        // Without helpers, the name is looked up as usual.
+        let ctx = Context::new(Path::new("."));
+        assert_eq!(render(&ctx, "{{#if_eq}}x{{/if_eq}}"), Ok("".to_string()));
+    }
+}
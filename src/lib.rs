@@ -6,6 +6,9 @@
 extern crate serialize;
 extern crate unicode;
 
+#[cfg(feature = "serde")]
+extern crate serde;
+
 #[macro_use]
 extern crate log;
 
@@ -13,22 +16,40 @@ pub use builder::{MapBuilder, VecBuilder};
 pub use context::Context;
 pub use data::Data;
 pub use encoder::{Encoder, EncoderResult};
-pub use error::Error;
-pub use template::Template;
+pub use error::{Diagnostic, Error, ParseErrorKind};
+pub use escape::Escaper;
+pub use filter::Filter;
+pub use helper::Helper;
+pub use loader::{FileLoader, PartialLoader};
+pub use map::Map;
+pub use parser::Span;
+pub use registry::Registry;
+#[cfg(feature = "serde")]
+pub use serializer::Serializer;
+pub use template::{LambdaContext, Renderer, Template};
 
 use std::path::Path;
 
+pub mod ast;
 pub mod builder;
+pub mod escape;
+pub mod filter;
+pub mod helper;
 mod data;
 mod encoder;
 mod error;
+mod loader;
+mod map;
 mod parser;
 mod context;
 mod compiler;
+mod registry;
+#[cfg(feature = "serde")]
+mod serializer;
 mod template;
 
 /// Compiles a template from an `Iterator<char>`.
-pub fn compile_iter<T: Iterator<Item=char>>(iter: T) -> Template {
+pub fn compile_iter<T: Iterator<Item=char>>(iter: T) -> Result<Template, Error> {
     Context::new(".").compile(iter)
 }
 
@@ -39,6 +60,6 @@ pub fn compile_path(path: &Path) -> Result<Template, Error> {
 }
 
 /// Compiles a template from a string.
-pub fn compile_str(template: &str) -> Template {
+pub fn compile_str(template: &str) -> Result<Template, Error> {
     compile_iter(template.chars())
 }
@@ -0,0 +1,168 @@
+use std::borrow::ToOwned;
+use std::collections::HashMap;
+use std::error::FromError;
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::{PathBuf, AsPath};
+
+use error::Error;
+
+/// `PartialLoader` finds the source of partials by name. A `Context` uses it
+/// to load the partials of the templates it compiles.
+pub trait PartialLoader {
+    /// Returns the source of the partial `name`, or
+    /// `Error::PartialNotFound` if there is no such partial.
+    fn load(&self, name: &str) -> Result<String, Error>;
+
+    /// Returns when the partial `name` was last modified, if that is known.
+    /// A `Registry` with hot reloading uses this to find partials that need
+    /// to be compiled again.
+    fn modified(&self, _name: &str) -> Option<u64> { None }
+}
+
+/// `FileLoader` loads partials from the files in a directory. The partial
+/// `name` is read from `path/name.extension`. Partials can be in
+/// subdirectories, but names that would reach outside of the directory,
+/// with `..` or a leading `/`, aren't found.
+#[derive(Clone, Debug)]
+pub struct FileLoader {
+    pub path: PathBuf,
+    pub extension: String,
+}
+
+impl FileLoader {
+    /// Creates a loader for the files in `path` with the given extension.
+    pub fn new<P>(path: P, extension: &str) -> FileLoader where P: AsPath {
+        FileLoader {
+            path: path.as_path().to_owned(),
+            extension: extension.to_string(),
+        }
+    }
+
+    fn partial_path(&self, name: &str) -> Option<PathBuf> {
+        let outside = name.starts_with("/") || name.starts_with("\\") ||
+            name.split(|c: char| c == '/' || c == '\\').any(|part| part == "..");
+
+        if outside {
+            None
+        } else {
+            Some(self.path.join(&format!("{}.{}", name, self.extension)))
+        }
+    }
+}
+
+impl PartialLoader for FileLoader {
+    fn load(&self, name: &str) -> Result<String, Error> {
+        let path = match self.partial_path(name) {
+            Some(path) => path,
+            None => { return Err(Error::PartialNotFound(name.to_string())); }
+        };
+
+        let mut file = match File::open(&path) {
+            Ok(file) => file,
+            Err(ref err) if err.kind() == io::ErrorKind::FileNotFound => {
+                return Err(Error::PartialNotFound(name.to_string()));
+            }
+            Err(err) => { return Err(FromError::from_error(err)); }
+        };
+
+        let mut partial = String::new();
+        try!(file.read_to_string(&mut partial));
+
+        Ok(partial)
+    }
+
+    fn modified(&self, name: &str) -> Option<u64> {
+        let path = match self.partial_path(name) {
+            Some(path) => path,
+            None => { return None; }
+        };
+
+        match fs::metadata(&path) {
+            Ok(metadata) => Some(metadata.modified()),
+            Err(_) => None,
+        }
+    }
+}
+
+/// Partials can be kept in memory, keyed by their name.
+impl PartialLoader for HashMap<String, String> {
+    fn load(&self, name: &str) -> Result<String, Error> {
+        match self.get(name) {
+            Some(partial) => Ok(partial.clone()),
+            None => Err(Error::PartialNotFound(name.to_string())),
+        }
+    }
+}
+
+/// A list of loaders is searched in order, so a `Vec<FileLoader>` looks for
+/// partials in several directories.
+impl<L: PartialLoader> PartialLoader for Vec<L> {
+    fn load(&self, name: &str) -> Result<String, Error> {
+        for loader in self.iter() {
+            match loader.load(name) {
+                Err(Error::PartialNotFound(_)) => { }
+                result => { return result; }
+            }
+        }
+
+        Err(Error::PartialNotFound(name.to_string()))
+    }
+
+    fn modified(&self, name: &str) -> Option<u64> {
+        self.iter().filter_map(|loader| loader.modified(name)).next()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::path::Path;
+
+    use context::Context;
+    use data::Data;
+    use error::Error;
+    use map::Map;
+    use super::{FileLoader, PartialLoader};
+
+    #[test]
+    fn test_file_loader() {
+        let loader = FileLoader::new(Path::new("src/test-data"), "mustache");
+
+        assert_eq!(loader.load("user"), Ok("<strong>{{name}}</strong>\n".to_string()));
+        assert_eq!(loader.load("missing"), Err(Error::PartialNotFound("missing".to_string())));
+
+        // Partials outside of the directory aren't found.
+        let loader = FileLoader::new(Path::new("src/test-data/"), "mustache");
+        for name in ["../test-data/user", "../../src/test-data/user", "/etc/passwd"].iter() {
+            assert_eq!(loader.load(name), Err(Error::PartialNotFound(name.to_string())));
+        }
+    }
+
+    #[test]
+    fn test_map_loader() {
+        let mut partials = HashMap::new();
+        partials.insert("user".to_string(), "{{name}}!".to_string());
+
+        let template = Context::new(Path::new("."))
+            .with_loader(partials)
+            .compile("{{>user}}".chars())
+            .unwrap();
+
+        let mut ctx = Map::new();
+        ctx.insert("name".to_string(), Data::Str("a".to_string()));
+
+        assert_eq!(template.render_data_to_string(&Data::Map(ctx)), Ok("a!".to_string()));
+    }
+
+    #[test]
+    fn test_vec_loader() {
+        let loader = vec!(
+            FileLoader::new(Path::new("src"), "mustache"),
+            FileLoader::new(Path::new("src/test-data"), "mustache")
+        );
+
+        assert_eq!(loader.load("test"), Ok("{{foo}}bar\n".to_string()));
+        assert_eq!(loader.load("missing"), Err(Error::PartialNotFound("missing".to_string())));
+    }
+}
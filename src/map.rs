@@ -0,0 +1,229 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::iter::{FromIterator, IntoIterator};
+use std::mem;
+use std::slice;
+use std::vec;
+
+use data::Data;
+
+/// `Map` is the map of `Data::Map`. It keeps its entries in the order they
+/// were first inserted, so encoding a struct keeps the order its fields were
+/// declared in, and anything rendered from a map comes out the same way
+/// every time.
+pub struct Map {
+    entries: Vec<(String, Data)>,
+    /// The position of each key in `entries`.
+    index: HashMap<String, usize>,
+}
+
+impl Map {
+    /// Creates an empty `Map`.
+    pub fn new() -> Map {
+        Map {
+            entries: Vec::new(),
+            index: HashMap::new(),
+        }
+    }
+
+    /// Returns the number of entries in the map.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Returns whether the map has no entries.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Returns the value of `key`.
+    pub fn get(&self, key: &str) -> Option<&Data> {
+        match self.index.get(key) {
+            Some(&i) => Some(&self.entries[i].1),
+            None => None,
+        }
+    }
+
+    /// Returns the value of `key` mutably.
+    pub fn get_mut(&mut self, key: &str) -> Option<&mut Data> {
+        match self.index.get(key) {
+            Some(&i) => Some(&mut self.entries[i].1),
+            None => None,
+        }
+    }
+
+    /// Returns whether the map has a value for `key`.
+    pub fn contains_key(&self, key: &str) -> bool {
+        self.index.contains_key(key)
+    }
+
+    /// Sets the value of `key`, returning its old value. A key that is
+    /// already in the map keeps its place.
+    pub fn insert(&mut self, key: String, value: Data) -> Option<Data> {
+        if let Some(&i) = self.index.get(&key) {
+            return Some(mem::replace(&mut self.entries[i].1, value));
+        }
+
+        self.index.insert(key.clone(), self.entries.len());
+        self.entries.push((key, value));
+        None
+    }
+
+    /// Removes `key` from the map, returning its value. The entries after it
+    /// keep their order.
+    pub fn remove(&mut self, key: &str) -> Option<Data> {
+        let i = match self.index.remove(key) {
+            Some(i) => i,
+            None => { return None; }
+        };
+
+        let (_, value) = self.entries.remove(i);
+        for &(ref key, _) in self.entries[i..].iter() {
+            if let Some(pos) = self.index.get_mut(key) {
+                *pos -= 1;
+            }
+        }
+
+        Some(value)
+    }
+
+    /// Iterates over the entries in the order they were inserted.
+    pub fn iter(&self) -> Iter {
+        Iter { iter: self.entries.iter() }
+    }
+
+    /// Iterates over the keys in the order they were inserted.
+    pub fn keys(&self) -> Keys {
+        Keys { iter: self.entries.iter() }
+    }
+}
+
+/// Maps are equal if they have the same entries, whatever their order.
+impl PartialEq for Map {
+    fn eq(&self, other: &Map) -> bool {
+        self.len() == other.len() && self.iter().all(|(key, value)| {
+            other.get(key) == Some(value)
+        })
+    }
+}
+
+impl fmt::Debug for Map {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        try!(write!(f, "{{"));
+        for (i, (key, value)) in self.iter().enumerate() {
+            if i != 0 {
+                try!(write!(f, ", "));
+            }
+            try!(write!(f, "{:?}: {:?}", key, value));
+        }
+        write!(f, "}}")
+    }
+}
+
+impl FromIterator<(String, Data)> for Map {
+    fn from_iter<T: IntoIterator<Item=(String, Data)>>(iter: T) -> Map {
+        let mut map = Map::new();
+        for (key, value) in iter.into_iter() {
+            map.insert(key, value);
+        }
+        map
+    }
+}
+
+impl IntoIterator for Map {
+    type Item = (String, Data);
+    type IntoIter = vec::IntoIter<(String, Data)>;
+
+    fn into_iter(self) -> vec::IntoIter<(String, Data)> {
+        self.entries.into_iter()
+    }
+}
+
+impl<'a> IntoIterator for &'a Map {
+    type Item = (&'a String, &'a Data);
+    type IntoIter = Iter<'a>;
+
+    fn into_iter(self) -> Iter<'a> {
+        self.iter()
+    }
+}
+
+/// An iterator over the entries of a `Map`, in insertion order.
+pub struct Iter<'a> {
+    iter: slice::Iter<'a, (String, Data)>,
+}
+
+impl<'a> Iterator for Iter<'a> {
+    type Item = (&'a String, &'a Data);
+
+    fn next(&mut self) -> Option<(&'a String, &'a Data)> {
+        self.iter.next().map(|&(ref key, ref value)| (key, value))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.iter.size_hint()
+    }
+}
+
+/// An iterator over the keys of a `Map`, in insertion order.
+pub struct Keys<'a> {
+    iter: slice::Iter<'a, (String, Data)>,
+}
+
+impl<'a> Iterator for Keys<'a> {
+    type Item = &'a String;
+
+    fn next(&mut self) -> Option<&'a String> {
+        self.iter.next().map(|&(ref key, _)| key)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.iter.size_hint()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use data::Data;
+    use super::Map;
+
+    fn keys(map: &Map) -> Vec<String> {
+        map.keys().cloned().collect()
+    }
+
+    #[test]
+    fn test_insertion_order() {
+        let mut map = Map::new();
+        map.insert("b".to_string(), Data::UInt(1));
+        map.insert("a".to_string(), Data::UInt(2));
+        map.insert("c".to_string(), Data::UInt(3));
+        assert_eq!(keys(&map), vec!("b".to_string(), "a".to_string(), "c".to_string()));
+
+        // Replacing a value keeps its place.
+        assert_eq!(map.insert("a".to_string(), Data::UInt(4)), Some(Data::UInt(2)));
+        assert_eq!(keys(&map), vec!("b".to_string(), "a".to_string(), "c".to_string()));
+        assert_eq!(map.get("a"), Some(&Data::UInt(4)));
+
+        assert_eq!(map.remove("b"), Some(Data::UInt(1)));
+        assert_eq!(map.remove("b"), None);
+        assert_eq!(keys(&map), vec!("a".to_string(), "c".to_string()));
+        assert_eq!(map.get("c"), Some(&Data::UInt(3)));
+        assert_eq!(format!("{:?}", map), "{\"a\": UInt(4), \"c\": UInt(3)}".to_string());
+    }
+
+    #[test]
+    fn test_eq_ignores_order() {
+        let a: Map = vec!(
+            ("a".to_string(), Data::Bool(true)),
+            ("b".to_string(), Data::Null)
+        ).into_iter().collect();
+
+        let b: Map = vec!(
+            ("b".to_string(), Data::Null),
+            ("a".to_string(), Data::Bool(true))
+        ).into_iter().collect();
+
+        assert_eq!(a, b);
+        assert!(a != Map::new());
+    }
+}
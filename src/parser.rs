@@ -1,25 +1,282 @@
+use std::error::FromError;
 use std::mem;
 
 use unicode::str::UnicodeStr;
-use self::Token::{Text, ETag, UTag, Section, IncompleteSection, Partial};
+
+use ast;
+use error::{Diagnostic, Error, ParseErrorKind};
+use self::Token::{Text, ETag, UTag, Section, IncompleteSection, Partial, DynamicPartial, Parent,
+                  Block, Comment, Delimiters};
 use self::TokenClass::{Normal, StandAlone, WhiteSpace};
 use self::ParserState::{TEXT, OTAG, TAG, CTAG};
 
+/// `Span` is the location of a `Token` in the template source.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Span {
+    /// The byte offset of the start of the token.
+    pub start: usize,
+    /// The byte offset just past the end of the token.
+    pub end: usize,
+    /// The line the token starts on, counting from 1.
+    pub line: usize,
+    /// The column the token starts on, counting from 1.
+    pub col: usize,
+}
+
+/// `Tag` is what a variable or a section looks up: the path of a value, or
+/// of a helper along with its arguments, and, for variables, the filters
+/// the value is piped through.
+#[derive(Clone, Debug)]
+pub struct Tag {
+    pub path: Vec<String>,
+    pub args: Vec<ast::Argument>,
+    pub filters: Vec<ast::Filter>,
+}
+
+impl Tag {
+    /// Creates a tag that looks up `path`, without arguments or filters.
+    pub fn new(path: Vec<String>) -> Tag {
+        Tag {
+            path: path,
+            args: Vec::new(),
+            filters: Vec::new(),
+        }
+    }
+}
+
 /// `Token` is a section of a compiled mustache string.
 #[derive(Clone, Debug)]
 pub enum Token {
-    Text(String),
-    ETag(Vec<String>, String),
-    UTag(Vec<String>, String),
-    Section(Vec<String>, bool, Vec<Token>, String, String, String, String, String),
-    IncompleteSection(Vec<String>, bool, String, bool),
-    Partial(String, String, String),
+    Text(String, Span),
+    ETag(Tag, String, Span),
+    UTag(Tag, String, Span),
+    Section(Tag, bool, Vec<Token>, String, String, String, String, String, Span),
+    IncompleteSection(Tag, SectionKind, String, bool, Span),
+    Partial(String, String, String, Span),
+    DynamicPartial(Vec<String>, String, String, Span),
+    Parent(String, String, Vec<Token>, String, String, String, Span),
+    Block(String, String, Vec<Token>, String, String, String, Span),
+    Comment(String, String, Span),
+    Delimiters(String, String, String, Span),
+}
+
+/// `SectionKind` is what an `IncompleteSection` turns into once it is closed.
+#[derive(Clone, Debug)]
+pub enum SectionKind {
+    Normal,
+    Inverted,
+    /// A `{{<parent}}`, along with the whitespace it is indented with.
+    Parent(String),
+    /// A `{{$block}}`, along with the whitespace it is indented with.
+    Block(String),
+}
+
+impl Token {
+    /// Returns where the token is located in the template source.
+    pub fn span(&self) -> Span {
+        match *self {
+            Text(_, span)
+            | ETag(_, _, span)
+            | UTag(_, _, span)
+            | Section(_, _, _, _, _, _, _, _, span)
+            | IncompleteSection(_, _, _, _, span)
+            | Partial(_, _, _, span)
+            | DynamicPartial(_, _, _, span)
+            | Parent(_, _, _, _, _, _, span)
+            | Block(_, _, _, _, _, _, span)
+            | Comment(_, _, span)
+            | Delimiters(_, _, _, span) => span,
+        }
+    }
+
+    /// Converts the token into its public syntax tree node.
+    pub fn to_node(&self) -> ast::Node {
+        match *self {
+            Text(ref text, span) => {
+                let mut node = ast::Text::new(text.clone());
+                node.span = span;
+                ast::Node::Text(node)
+            }
+            ETag(ref name, ref tag, span) | UTag(ref name, ref tag, span) => {
+                let mut node = ast::Variable::new(name.path.clone());
+                node.args = name.args.clone();
+                node.escaped = match *self { ETag(..) => true, _ => false };
+                node.filters = name.filters.clone();
+                node.tag = tag.clone();
+                node.span = span;
+                ast::Node::Variable(node)
+            }
+            Section(ref name,
+                    inverted,
+                    ref children,
+                    ref otag,
+                    ref osection,
+                    ref src,
+                    ref csection,
+                    ref ctag,
+                    span) => {
+                let mut node = ast::Section::new(name.path.clone(), to_nodes(children));
+                node.args = name.args.clone();
+                node.open_tag = osection.clone();
+                node.source = src.clone();
+                node.close_tag = csection.clone();
+                node.otag = otag.clone();
+                node.ctag = ctag.clone();
+                node.span = span;
+
+                if inverted {
+                    ast::Node::InvertedSection(node)
+                } else {
+                    ast::Node::Section(node)
+                }
+            }
+            Partial(ref name, ref indent, ref tag, span) => {
+                let mut node = ast::Partial::new(name.clone());
+                node.indent = indent.clone();
+                node.tag = tag.clone();
+                node.span = span;
+                ast::Node::Partial(node)
+            }
+            DynamicPartial(ref path, ref indent, ref tag, span) => {
+                let mut node = ast::DynamicPartial::new(path.clone());
+                node.indent = indent.clone();
+                node.tag = tag.clone();
+                node.span = span;
+                ast::Node::DynamicPartial(node)
+            }
+            Parent(ref name, ref indent, ref children, ref osection, ref src, ref csection, span) => {
+                let mut node = ast::Parent::new(name.clone(), to_nodes(children));
+                node.indent = indent.clone();
+                node.open_tag = osection.clone();
+                node.source = src.clone();
+                node.close_tag = csection.clone();
+                node.span = span;
+                ast::Node::Parent(node)
+            }
+            Block(ref name, ref indent, ref children, ref osection, ref src, ref csection, span) => {
+                let mut node = ast::Block::new(name.clone(), to_nodes(children));
+                node.indent = indent.clone();
+                node.open_tag = osection.clone();
+                node.source = src.clone();
+                node.close_tag = csection.clone();
+                node.span = span;
+                ast::Node::Block(node)
+            }
+            Comment(ref text, ref tag, span) => {
+                let mut node = ast::Comment::new(text.clone());
+                node.tag = tag.clone();
+                node.span = span;
+                ast::Node::Comment(node)
+            }
+            Delimiters(ref otag, ref ctag, ref tag, span) => {
+                let mut node = ast::Delimiters::new(otag.clone(), ctag.clone());
+                node.tag = tag.clone();
+                node.span = span;
+                ast::Node::Delimiters(node)
+            }
+            IncompleteSection(..) => unreachable!(),
+        }
+    }
+
+    /// Converts a syntax tree node back into a token.
+    pub fn from_node(node: &ast::Node) -> Token {
+        match *node {
+            ast::Node::Text(ref text) => Text(text.text.clone(), text.span),
+            ast::Node::Variable(ref variable) => {
+                let name = Tag {
+                    path: variable.path.clone(),
+                    args: variable.args.clone(),
+                    filters: variable.filters.clone(),
+                };
+
+                if variable.escaped {
+                    ETag(name, variable.tag.clone(), variable.span)
+                } else {
+                    UTag(name, variable.tag.clone(), variable.span)
+                }
+            }
+            ast::Node::Section(ref section) | ast::Node::InvertedSection(ref section) => {
+                let inverted = match *node { ast::Node::InvertedSection(..) => true, _ => false };
+                let name = Tag {
+                    path: section.path.clone(),
+                    args: section.args.clone(),
+                    filters: Vec::new(),
+                };
+
+                Section(name,
+                        inverted,
+                        from_nodes(&section.children),
+                        section.otag.clone(),
+                        section.open_tag.clone(),
+                        section.source.clone(),
+                        section.close_tag.clone(),
+                        section.ctag.clone(),
+                        section.span)
+            }
+            ast::Node::Partial(ref partial) => {
+                Partial(partial.name.clone(),
+                        partial.indent.clone(),
+                        partial.tag.clone(),
+                        partial.span)
+            }
+            ast::Node::DynamicPartial(ref partial) => {
+                DynamicPartial(partial.path.clone(),
+                               partial.indent.clone(),
+                               partial.tag.clone(),
+                               partial.span)
+            }
+            ast::Node::Parent(ref parent) => {
+                Parent(parent.name.clone(),
+                       parent.indent.clone(),
+                       from_nodes(&parent.children),
+                       parent.open_tag.clone(),
+                       parent.source.clone(),
+                       parent.close_tag.clone(),
+                       parent.span)
+            }
+            ast::Node::Block(ref block) => {
+                Block(block.name.clone(),
+                      block.indent.clone(),
+                      from_nodes(&block.children),
+                      block.open_tag.clone(),
+                      block.source.clone(),
+                      block.close_tag.clone(),
+                      block.span)
+            }
+            ast::Node::Comment(ref comment) => {
+                Comment(comment.text.clone(), comment.tag.clone(), comment.span)
+            }
+            ast::Node::Delimiters(ref delimiters) => {
+                Delimiters(delimiters.otag.clone(),
+                           delimiters.ctag.clone(),
+                           delimiters.tag.clone(),
+                           delimiters.span)
+            }
+        }
+    }
+}
+
+/// Converts a series of tokens into their public syntax tree nodes.
+pub fn to_nodes(tokens: &[Token]) -> Vec<ast::Node> {
+    tokens.iter().map(|token| token.to_node()).collect()
+}
+
+/// Converts a series of syntax tree nodes back into tokens.
+pub fn from_nodes(nodes: &[ast::Node]) -> Vec<Token> {
+    nodes.iter().map(Token::from_node).collect()
+}
+
+#[derive(Clone, Copy)]
+struct Position {
+    offset: usize,
+    line: usize,
+    col: usize,
 }
 
 enum TokenClass {
     Normal,
     StandAlone,
-    WhiteSpace(String, usize),
+    WhiteSpace(usize, String, usize),
 }
 
 /// `Parser` parses a string into a series of `Token`s.
@@ -27,8 +284,11 @@ pub struct Parser<'a, T: 'a> {
     reader: &'a mut T,
     ch: Option<char>,
     lookahead: Option<char>,
+    offset: usize,
     line: usize,
     col: usize,
+    tag_start: Position,
+    text_start: Position,
     content: String,
     state: ParserState,
     otag: String,
@@ -38,6 +298,11 @@ pub struct Parser<'a, T: 'a> {
     tag_position: usize,
     tokens: Vec<Token>,
     partials: Vec<String>,
+    diagnostics: Vec<Diagnostic>,
+    /// Whether variables can be piped through filters.
+    filters: bool,
+    /// Whether variables and sections can call helpers with arguments.
+    helpers: bool,
 }
 
 enum ParserState { TEXT, OTAG, TAG, CTAG }
@@ -48,8 +313,11 @@ impl<'a, T: Iterator<Item=char>> Parser<'a, T> {
             reader: reader,
             ch: None,
             lookahead: None,
+            offset: 0,
             line: 1,
             col: 1,
+            tag_start: Position { offset: 0, line: 1, col: 1 },
+            text_start: Position { offset: 0, line: 1, col: 1 },
             content: String::new(),
             state: TEXT,
             otag: otag.to_string(),
@@ -59,20 +327,41 @@ impl<'a, T: Iterator<Item=char>> Parser<'a, T> {
             tag_position: 0,
             tokens: Vec::new(),
             partials: Vec::new(),
+            diagnostics: Vec::new(),
+            filters: false,
+            helpers: false,
         };
 
         parser.bump();
         parser
     }
 
-    fn bump(&mut self) {
-        match self.lookahead.take() {
-            None => { self.ch = self.reader.next(); }
-            Some(ch) => { self.ch = Some(ch); }
+    /// Sets whether variables can be piped through filters, as in
+    /// `{{name | upper}}`. Without them, `|` is just part of the name.
+    pub fn with_filters(self, filters: bool) -> Parser<'a, T> {
+        Parser {
+            filters: filters,
+            .. self
         }
+    }
 
+    /// Sets whether variables and sections can call helpers with arguments,
+    /// as in `{{#if_eq status "active"}}`. Without them, the spaces are just
+    /// part of the name.
+    pub fn with_helpers(self, helpers: bool) -> Parser<'a, T> {
+        Parser {
+            helpers: helpers,
+            .. self
+        }
+    }
+
+    fn bump(&mut self) {
+        // Advance the position past the current character, so that `offset`,
+        // `line` and `col` always point at `self.ch`.
         match self.ch {
             Some(ch) => {
+                self.offset += ch.len_utf8();
+
                 if ch == '\n' {
                     self.line += 1;
                     self.col = 1;
@@ -82,6 +371,11 @@ impl<'a, T: Iterator<Item=char>> Parser<'a, T> {
             }
             None => { }
         }
+
+        match self.lookahead.take() {
+            None => { self.ch = self.reader.next(); }
+            Some(ch) => { self.ch = Some(ch); }
+        }
     }
 
     fn peek(&mut self) -> Option<char> {
@@ -94,6 +388,23 @@ impl<'a, T: Iterator<Item=char>> Parser<'a, T> {
         }
     }
 
+    fn position(&self) -> Position {
+        Position {
+            offset: self.offset,
+            line: self.line,
+            col: self.col,
+        }
+    }
+
+    fn span(&self, start: Position, end: usize) -> Span {
+        Span {
+            start: start.offset,
+            end: end,
+            line: start.line,
+            col: start.col,
+        }
+    }
+
     fn ch_is(&self, ch: char) -> bool {
         match self.ch {
             Some(c) => c == ch,
@@ -102,7 +413,23 @@ impl<'a, T: Iterator<Item=char>> Parser<'a, T> {
     }
 
     /// Parse the template into tokens and a list of partial files.
-    pub fn parse(mut self) -> (Vec<Token>, Vec<String>) {
+    pub fn parse(self) -> Result<(Vec<Token>, Vec<String>), Error> {
+        let (tokens, partials, diagnostics) = self.parse_all();
+
+        match diagnostics.into_iter().next() {
+            Some(diagnostic) => Err(FromError::from_error(diagnostic)),
+            None => Ok((tokens, partials)),
+        }
+    }
+
+    /// Parse the whole template, recovering from any syntax errors, and
+    /// return every error that was found.
+    pub fn check(self) -> Vec<Diagnostic> {
+        let (_, _, diagnostics) = self.parse_all();
+        diagnostics
+    }
+
+    fn parse_all(mut self) -> (Vec<Token>, Vec<String>, Vec<Diagnostic>) {
         let mut curly_brace_tag = false;
 
         loop {
@@ -114,6 +441,8 @@ impl<'a, T: Iterator<Item=char>> Parser<'a, T> {
             match self.state {
                 TEXT => {
                     if ch == self.otag_chars[0] {
+                        self.tag_start = self.position();
+
                         if self.otag_chars.len() > 1 {
                             self.tag_position = 1;
                             self.state = OTAG;
@@ -159,6 +488,7 @@ impl<'a, T: Iterator<Item=char>> Parser<'a, T> {
                             self.state = CTAG;
                             self.bump();
                         } else {
+                            self.bump();
                             self.add_tag();
                             self.state = TEXT;
                         }
@@ -170,6 +500,7 @@ impl<'a, T: Iterator<Item=char>> Parser<'a, T> {
                 CTAG => {
                     if ch == self.ctag_chars[self.tag_position] {
                         if self.tag_position == self.ctag_chars.len() - 1 {
+                            self.bump();
                             self.add_tag();
                             self.state = TEXT;
                         } else {
@@ -179,9 +510,19 @@ impl<'a, T: Iterator<Item=char>> Parser<'a, T> {
                             self.bump();
                         }
                     } else {
-                        panic!("character {} is not part of CTAG: {}",
-                              ch,
-                              self.ctag_chars[self.tag_position]);
+                        let ctag: String = self.ctag_chars.iter()
+                            .take(self.tag_position)
+                            .map(|c| *c)
+                            .collect();
+                        let snippet = format!("{}{}{}{}", self.otag, self.content, ctag, ch);
+                        self.error(ParseErrorKind::InvalidClosingTag, snippet);
+
+                        // Resynchronize by treating the tag as if it was closed
+                        // here, which is usually what happened with an
+                        // unbalanced "{{{". The current character is then
+                        // parsed as text.
+                        self.add_tag();
+                        self.state = TEXT;
                     }
                 }
             }
@@ -190,23 +531,49 @@ impl<'a, T: Iterator<Item=char>> Parser<'a, T> {
         match self.state {
             TEXT => { self.add_text(); }
             OTAG => { self.not_otag(); self.add_text(); }
-            TAG => { panic!("unclosed tag"); }
-            CTAG => { self.not_ctag(); self.add_text(); }
+            TAG => { self.unclosed_tag(); }
+            CTAG => { self.not_ctag(); self.unclosed_tag(); }
         }
 
-        // Check that we don't have any incomplete sections.
-        for token in self.tokens.iter() {
-            match *token {
-                IncompleteSection(ref path, _, _, _) => {
-                    panic!("Unclosed mustache section {}", path.connect("."));
-              }
-              _ => {}
-            }
-        };
+        // Close any sections that are still open.
+        loop {
+            let pos = match self.last_incomplete_section() {
+                Some(pos) => pos,
+                None => { break; }
+            };
 
-        let Parser { tokens, partials, .. } = self;
+            let end = self.offset;
+            self.unclosed_section(pos, end);
+        }
+
+        let Parser { tokens, partials, diagnostics, .. } = self;
+
+        (tokens, partials, diagnostics)
+    }
+
+    /// Build a diagnostic located at the start of the current tag.
+    fn diagnostic(&self, kind: ParseErrorKind, snippet: String) -> Diagnostic {
+        Diagnostic {
+            kind: kind,
+            line: self.tag_start.line,
+            col: self.tag_start.col,
+            snippet: snippet,
+        }
+    }
+
+    /// Record a syntax error located at the start of the current tag.
+    fn error(&mut self, kind: ParseErrorKind, snippet: String) {
+        let diagnostic = self.diagnostic(kind, snippet);
+        self.diagnostics.push(diagnostic);
+    }
 
-        (tokens, partials)
+    fn unclosed_tag(&mut self) {
+        // Keep the unclosed tag around as text.
+        let content = format!("{}{}", self.otag, self.content);
+        self.error(ParseErrorKind::UnclosedTag, content.clone());
+        self.content = content;
+        self.text_start = self.tag_start;
+        self.add_text();
     }
 
     fn add_text(&mut self) {
@@ -214,10 +581,32 @@ impl<'a, T: Iterator<Item=char>> Parser<'a, T> {
             let mut content = String::new();
             mem::swap(&mut content, &mut self.content);
 
-            self.tokens.push(Text(content.to_string()));
+            // Text always runs up to the start of the next tag, or the end of
+            // the template.
+            let end = match self.ch {
+                None => self.offset,
+                Some(_) => self.tag_start.offset,
+            };
+            let span = self.span(self.text_start, end);
+
+            self.tokens.push(Text(content.to_string(), span));
         }
     }
 
+    /// Trims the whitespace at the end of the `Text` token at `index`,
+    /// starting at `pos`.
+    fn trim_text(&mut self, index: usize, s: &str, pos: usize) {
+        let span = match self.tokens[index] {
+            Text(_, span) => span,
+            _ => unreachable!(),
+        };
+
+        self.tokens[index] = Text(s.slice(0, pos).to_string(), Span {
+            end: span.start + pos,
+            .. span
+        });
+    }
+
     // This function classifies whether or not a token is standalone, or if it
     // has trailing whitespace. It's looking for this pattern:
     //
@@ -234,21 +623,36 @@ impl<'a, T: Iterator<Item=char>> Parser<'a, T> {
             }
         }
 
-        match self.tokens.last() {
+        self.classify_line()
+    }
+
+    // This function classifies whether or not only whitespace precedes the
+    // current token on its line, regardless of what follows it.
+    fn classify_line(&self) -> TokenClass {
+        // Comments and delimiter changes have already been classified, so
+        // look at the last token before them.
+        let index = self.tokens.iter().rposition(|token| {
+            match *token {
+                Comment(..) | Delimiters(..) => false,
+                _ => true,
+            }
+        });
+
+        match index.map(|index| (index, &self.tokens[index])) {
             // If the last token ends with a newline (or there is no previous
             // token), then this token is standalone.
             None => { StandAlone }
 
-            Some(&IncompleteSection(_, _, _, true)) => { StandAlone }
+            Some((_, &IncompleteSection(_, _, _, true, _))) => { StandAlone }
 
-            Some(&Text(ref s)) if !s.is_empty() => {
+            Some((index, &Text(ref s, _))) if !s.is_empty() => {
                 // Look for the last newline character that may have whitespace
                 // following it.
                 match s.rfind(|c:char| c == '\n' || !c.is_whitespace()) {
                     // It's all whitespace.
                     None => {
-                        if self.tokens.len() == 1 {
-                            WhiteSpace(s.to_string(), 0)
+                        if index == 0 {
+                            WhiteSpace(index, s.to_string(), 0)
                         } else {
                             Normal
                         }
@@ -258,7 +662,7 @@ impl<'a, T: Iterator<Item=char>> Parser<'a, T> {
                             if pos == s.len() - 1 {
                                 StandAlone
                             } else {
-                                WhiteSpace(s.to_string(), pos + 1)
+                                WhiteSpace(index, s.to_string(), pos + 1)
                             }
                         } else { Normal }
                     }
@@ -279,13 +683,12 @@ impl<'a, T: Iterator<Item=char>> Parser<'a, T> {
                 self.bump();
                 true
             }
-            WhiteSpace(s, pos) => {
+            WhiteSpace(index, s, pos) => {
                 if self.ch_is('\r') { self.bump(); }
                 self.bump();
 
                 // Trim the whitespace from the last token.
-                self.tokens.pop();
-                self.tokens.push(Text(s.slice(0, pos).to_string()));
+                self.trim_text(index, &s, pos);
 
                 true
             }
@@ -293,8 +696,17 @@ impl<'a, T: Iterator<Item=char>> Parser<'a, T> {
     }
 
     fn add_tag(&mut self) {
-        self.bump();
+        // Skip over a malformed tag so we can keep looking for errors.
+        if let Err(diagnostic) = self.parse_tag() {
+            self.diagnostics.push(diagnostic);
+        }
+
+        self.text_start = self.position();
+    }
+
+    fn parse_tag(&mut self) -> Result<(), Diagnostic> {
         let tag = format!("{}{}{}", self.otag, self.content, self.ctag);
+        let span = self.span(self.tag_start, self.offset);
 
         // Move the content to avoid a copy.
         let mut content = String::new();
@@ -303,121 +715,118 @@ impl<'a, T: Iterator<Item=char>> Parser<'a, T> {
 
         match content.char_at(0) {
             '!' => {
-                // ignore comments
                 self.eat_whitespace();
+
+                // Comments aren't rendered, but we keep them around for the
+                // syntax tree.
+                let text = content.slice(1, len).to_string();
+                self.tokens.push(Comment(text, tag, span));
             }
             '&' => {
-                let name = content.slice(1, len);
-                let name = self.check_content(name);
-                let name = name.split_terminator('.')
-                    .map(|x| x.to_string())
-                    .collect();
-                self.tokens.push(UTag(name, tag));
+                let name = try!(self.parse_variable(content.slice(1, len), &tag));
+                self.tokens.push(UTag(name, tag, span));
             }
             '{' => {
                 if content.ends_with("}") {
                     let name = content.slice(1, len - 1);
-                    let name = self.check_content(name);
-                    let name = name.split_terminator('.')
-                        .map(|x| x.to_string())
-                        .collect();
-                    self.tokens.push(UTag(name, tag));
-                } else { panic!("unbalanced \"{\" in tag"); }
+                    let name = try!(self.parse_variable(name, &tag));
+                    self.tokens.push(UTag(name, tag, span));
+                } else {
+                    return Err(self.diagnostic(ParseErrorKind::UnbalancedUnescapeTag, tag));
+                }
             }
             '#' => {
                 let newlined = self.eat_whitespace();
 
-                let name = self.check_content(content.slice(1, len));
-                let name = name.split_terminator('.')
-                    .map(|x| x.to_string())
-                    .collect();
-                self.tokens.push(IncompleteSection(name, false, tag, newlined));
+                let name = try!(self.check_content(content.slice(1, len), &tag));
+                let name = try!(self.parse_call(&name, &tag));
+                let kind = SectionKind::Normal;
+                self.tokens.push(IncompleteSection(name, kind, tag, newlined, span));
             }
             '^' => {
                 let newlined = self.eat_whitespace();
 
-                let name = self.check_content(content.slice(1, len));
-                let name = name.split_terminator('.')
-                    .map(|x| x.to_string())
-                    .collect();
-                self.tokens.push(IncompleteSection(name, true, tag, newlined));
+                // There's no way to invert what a helper renders, so inverted
+                // sections can't call one.
+                let name = try!(self.check_content(content.slice(1, len), &tag));
+                let name = try!(self.parse_call(&name, &tag));
+                if !name.args.is_empty() {
+                    return Err(self.diagnostic(ParseErrorKind::InvalidArgument, tag));
+                }
+
+                let kind = SectionKind::Inverted;
+                self.tokens.push(IncompleteSection(name, kind, tag, newlined, span));
             }
             '/' => {
                 self.eat_whitespace();
 
-                let name = self.check_content(content.slice(1, len));
-                let name = name.split_terminator('.')
-                    .map(|x| x.to_string())
-                    .collect();
-                let mut children: Vec<Token> = Vec::new();
-
-                loop {
-                    if self.tokens.len() == 0 {
-                        panic!("closing unopened section");
-                    }
-
-                    let last = self.tokens.pop();
-
-                    match last {
-                        Some(IncompleteSection(section_name, inverted, osection, _)) => {
-                            children.reverse();
-
-                            // Collect all the children's sources.
-                            let mut srcs = Vec::new();
-                            for child in children.iter() {
-                                match *child {
-                                    Text(ref s)
-                                    | ETag(_, ref s)
-                                    | UTag(_, ref s)
-                                    | Partial(_, _, ref s) => {
-                                        srcs.push(s.clone())
-                                    }
-                                    Section(_, _, _, _, ref osection, ref src, ref csection, _) => {
-                                        srcs.push(osection.clone());
-                                        srcs.push(src.clone());
-                                        srcs.push(csection.clone());
-                                    }
-                                    _ => panic!(),
-                                }
-                            }
+                let name = try!(self.check_content(content.slice(1, len), &tag));
 
-                            if section_name == name {
-                                // Cache the combination of all the sources in the
-                                // section. It's unfortunate, but we need to do this in
-                                // case the user uses a function to instantiate the
-                                // tag.
-                                let mut src = String::new();
-                                for s in srcs.iter() { src.push_str(&s); }
-
-                                self.tokens.push(
-                                    Section(
-                                        name,
-                                        inverted,
-                                        children,
-                                        self.otag.to_string(),
-                                        osection,
-                                        src.to_string(),
-                                        tag,
-                                        self.ctag.to_string()));
-                                break;
-                            } else {
-                                panic!("Unclosed section");
-                            }
+                // Parents and blocks keep their names whole, so compare the
+                // names as they were written. Sections compare their paths,
+                // so that `{{/.}}` closes `{{#.}}`.
+                let pos = self.tokens.iter().rposition(|token| {
+                    match *token {
+                        IncompleteSection(ref section_name, SectionKind::Parent(_), _, _, _)
+                        | IncompleteSection(ref section_name, SectionKind::Block(_), _, _, _) => {
+                            section_name.path.connect(".") == name
                         }
-                        _ => { match last {
-                            Some(last_token) => {children.push(last_token); }
-                            None => ()
-                            }
+                        IncompleteSection(ref section_name, _, _, _, _) => {
+                            section_name.path == path(&name)
                         }
+                        _ => false,
+                    }
+                });
+
+                let pos = match pos {
+                    Some(pos) => pos,
+                    None => {
+                        return Err(self.diagnostic(ParseErrorKind::UnopenedSection(name), tag));
                     }
+                };
+
+                // Any sections opened inside of this one were never closed, so
+                // report them and close them here.
+                loop {
+                    let inner = match self.last_incomplete_section() {
+                        Some(inner) if inner > pos => inner,
+                        _ => { break; }
+                    };
+
+                    self.unclosed_section(inner, span.start);
+                }
+
+                self.close_section(pos, tag, span.end);
+            }
+            '>' => { try!(self.add_partial(&content, tag, span)); }
+            '<' => {
+                let (newlined, indent) = self.eat_line_start();
+
+                // Parents are loaded along with the partials, unless their
+                // name comes from the data.
+                let name = try!(self.check_content(content.slice(1, len), &tag));
+                if !name.starts_with("*") {
+                    self.partials.push(name.clone());
                 }
+
+                let name = Tag::new(vec!(name));
+                let kind = SectionKind::Parent(indent);
+                self.tokens.push(IncompleteSection(name, kind, tag, newlined, span));
+            }
+            '$' => {
+                let (newlined, indent) = self.eat_line_start();
+
+                let name = try!(self.check_content(content.slice(1, len), &tag));
+
+                let name = Tag::new(vec!(name));
+                let kind = SectionKind::Block(indent);
+                self.tokens.push(IncompleteSection(name, kind, tag, newlined, span));
             }
-            '>' => { self.add_partial(&content, tag); }
             '=' => {
                 self.eat_whitespace();
 
                 if len > 2 && content.ends_with("=") {
-                    let s = self.check_content(content.slice(1, len - 1));
+                    let s = try!(self.check_content(content.slice(1, len - 1), &tag));
 
                     let pos = s.find(|c| {
                         let mut text = String::with_capacity(1);
@@ -425,7 +834,9 @@ impl<'a, T: Iterator<Item=char>> Parser<'a, T> {
                         text.is_whitespace()
                     });
                     let pos = match pos {
-                      None => { panic!("invalid change delimiter tag content"); }
+                      None => {
+                          return Err(self.diagnostic(ParseErrorKind::InvalidDelimiterTag, tag));
+                      }
                       Some(pos) => { pos }
                     };
 
@@ -435,34 +846,168 @@ impl<'a, T: Iterator<Item=char>> Parser<'a, T> {
                     let s2 = s.slice_from(pos);
                     let pos = s2.find(|c: char| !c.is_whitespace());
                     let pos = match pos {
-                      None => { panic!("invalid change delimiter tag content"); }
+                      None => {
+                          return Err(self.diagnostic(ParseErrorKind::InvalidDelimiterTag, tag));
+                      }
                       Some(pos) => { pos }
                     };
 
                     self.ctag = s2.slice_from(pos).to_string();
                     self.ctag_chars = self.ctag.chars().collect();
+
+                    self.tokens.push(Delimiters(self.otag.clone(), self.ctag.clone(), tag, span));
                 } else {
-                    panic!("invalid change delimiter tag content");
+                    return Err(self.diagnostic(ParseErrorKind::InvalidDelimiterTag, tag));
                 }
             }
             _ => {
-                // If the name is "." then we want the top element, which we represent with
-                // an empty name.
-                let name = self.check_content(&content);
-                let name = if name == "." {
-                    Vec::new()
-                } else {
-                    name.split_terminator('.')
-                        .map(|x| x.to_string())
-                        .collect()
-                };
+                let name = try!(self.parse_variable(&content, &tag));
+                self.tokens.push(ETag(name, tag, span));
+            }
+        }
+
+        Ok(())
+    }
+
+    fn last_incomplete_section(&self) -> Option<usize> {
+        self.tokens.iter().rposition(|token| {
+            match *token {
+                IncompleteSection(..) => true,
+                _ => false,
+            }
+        })
+    }
+
+    /// Report that the `IncompleteSection` at `pos` was never closed, and
+    /// close it at `end`.
+    fn unclosed_section(&mut self, pos: usize, end: usize) {
+        let diagnostic = match self.tokens[pos] {
+            IncompleteSection(ref name, _, ref osection, _, span) => {
+                Diagnostic {
+                    kind: ParseErrorKind::UnclosedSection(name.path.connect(".")),
+                    line: span.line,
+                    col: span.col,
+                    snippet: osection.clone(),
+                }
+            }
+            _ => unreachable!(),
+        };
+
+        self.diagnostics.push(diagnostic);
+        self.close_section(pos, String::new(), end);
+    }
+
+    /// Replace the `IncompleteSection` at `pos` with a `Section` containing
+    /// all the tokens that follow it, and that ends at `end`.
+    fn close_section(&mut self, pos: usize, csection: String, end: usize) {
+        let mut children: Vec<Token> = Vec::new();
+        while self.tokens.len() > pos + 1 {
+            children.push(self.tokens.pop().unwrap());
+        }
+        children.reverse();
+
+        let (name, kind, osection, span) = match self.tokens.pop() {
+            Some(IncompleteSection(name, kind, osection, _, span)) => {
+                (name, kind, osection, span)
+            }
+            _ => unreachable!(),
+        };
 
-                self.tokens.push(ETag(name, tag));
+        // Collect all the children's sources.
+        let mut srcs = Vec::new();
+        for child in children.iter() {
+            match *child {
+                Text(ref s, _)
+                | ETag(_, ref s, _)
+                | UTag(_, ref s, _)
+                | Partial(_, _, ref s, _)
+                | DynamicPartial(_, _, ref s, _)
+                | Comment(_, ref s, _)
+                | Delimiters(_, _, ref s, _) => {
+                    srcs.push(s.clone())
+                }
+                Section(_, _, _, _, ref osection, ref src, ref csection, _, _)
+                | Parent(_, _, _, ref osection, ref src, ref csection, _)
+                | Block(_, _, _, ref osection, ref src, ref csection, _) => {
+                    srcs.push(osection.clone());
+                    srcs.push(src.clone());
+                    srcs.push(csection.clone());
+                }
+                _ => panic!(),
             }
         }
+
+        // Cache the combination of all the sources in the section. It's
+        // unfortunate, but we need to do this in case the user uses a
+        // function to instantiate the tag.
+        let mut src = String::new();
+        for s in srcs.iter() { src.push_str(&s); }
+
+        let span = Span { end: end, .. span };
+        let inverted = match kind { SectionKind::Inverted => true, _ => false };
+
+        let token = match kind {
+            SectionKind::Normal | SectionKind::Inverted => {
+                Section(
+                    name,
+                    inverted,
+                    children,
+                    self.otag.to_string(),
+                    osection,
+                    src,
+                    csection,
+                    self.ctag.to_string(),
+                    span)
+            }
+            SectionKind::Parent(indent) => {
+                Parent(name.path.connect("."), indent, children, osection, src, csection, span)
+            }
+            SectionKind::Block(indent) => {
+                Block(name.path.connect("."), indent, children, osection, src, csection, span)
+            }
+        };
+
+        self.tokens.push(token);
     }
 
-    fn add_partial(&mut self, content: &str, tag: String) {
+    // Parents and blocks are usually written on lines of their own, often
+    // right after one another, as in `{{<parent}}{{$block}}`. So if the tag
+    // starts a line, and only the end of the line or another tag follows
+    // it, we treat it as standalone, and strip its indentation so it can be
+    // applied to what it renders instead.
+    fn eat_line_start(&mut self) -> (bool, String) {
+        let line_end = match self.ch {
+            None | Some('\n') => true,
+            Some('\r') => self.peek() == Some('\n'),
+            Some(ch) => self.otag_chars.first() == Some(&ch),
+        };
+
+        // Text after the tag, as in `{{$block}}text`, keeps it from being
+        // standalone, and the text keeps its indentation.
+        if !line_end {
+            return (false, "".to_string());
+        }
+
+        let indent = match self.classify_line() {
+            Normal => { return (false, "".to_string()); }
+            StandAlone => "".to_string(),
+            WhiteSpace(index, s, pos) => {
+                // Trim the whitespace from the last token.
+                self.trim_text(index, &s, pos);
+
+                s.slice(pos, s.len()).to_string()
+            }
+        };
+
+        if self.ch_is('\n') || (self.ch_is('\r') && self.peek() == Some('\n')) {
+            if self.ch_is('\r') { self.bump(); }
+            self.bump();
+        }
+
+        (true, indent)
+    }
+
+    fn add_partial(&mut self, content: &str, tag: String, span: Span) -> Result<(), Diagnostic> {
         let indent = match self.classify_token() {
             Normal => "".to_string(),
             StandAlone => {
@@ -470,15 +1015,14 @@ impl<'a, T: Iterator<Item=char>> Parser<'a, T> {
                 self.bump();
                 "".to_string()
             }
-            WhiteSpace(s, pos) => {
+            WhiteSpace(index, s, pos) => {
                 if self.ch_is('\r') { self.bump(); }
                 self.bump();
 
                 let ws = s.slice(pos, s.len());
 
                 // Trim the whitespace from the last token.
-                self.tokens.pop();
-                self.tokens.push(Text(s.slice(0, pos).to_string()));
+                self.trim_text(index, &s, pos);
 
                 ws.to_string()
             }
@@ -488,10 +1032,23 @@ impl<'a, T: Iterator<Item=char>> Parser<'a, T> {
         // partial. So instead, we'll cache the partials we used and look them
         // up later.
         let name = content.slice(1, content.len());
-        let name = self.check_content(name);
+        let name = try!(self.check_content(name, &tag));
+
+        // A `{{>*name}}` partial is named by a value in the data, so it can
+        // only be loaded when it is rendered.
+        if name.starts_with("*") {
+            let path = try!(self.check_content(name.slice_from(1), &tag));
+            let path = path.split_terminator('.')
+                .map(|x| x.to_string())
+                .collect();
 
-        self.tokens.push(Partial(name.to_string(), indent, tag));
-        self.partials.push(name);
+            self.tokens.push(DynamicPartial(path, indent, tag, span));
+        } else {
+            self.tokens.push(Partial(name.to_string(), indent, tag, span));
+            self.partials.push(name);
+        }
+
+        Ok(())
     }
 
     fn not_otag(&mut self) {
@@ -512,11 +1069,180 @@ impl<'a, T: Iterator<Item=char>> Parser<'a, T> {
         }
     }
 
-    fn check_content(&self, content: &str) -> String {
+    /// Parses the content of a variable tag into its path, the arguments of
+    /// the helper it calls, if any, and the filters piped after it, like
+    /// `name | truncate:20`.
+    fn parse_variable(&self, content: &str, tag: &str) -> Result<Tag, Diagnostic> {
+        let content = try!(self.check_content(content, tag));
+
+        let invalid = || self.diagnostic(ParseErrorKind::InvalidArgument, tag.to_string());
+
+        let mut parts = if self.filters {
+            match split_quoted(&content, '|') {
+                Some(parts) => parts,
+                None => { return Err(invalid()); }
+            }
+        } else {
+            vec!(content)
+        };
+
+        let name = parts.remove(0);
+        if name.len() == 0 {
+            return Err(self.diagnostic(ParseErrorKind::EmptyTag, tag.to_string()));
+        }
+
+        let mut filters = Vec::new();
+        for part in parts.iter() {
+            let mut args = match split_quoted(part, ':') {
+                Some(args) => args.into_iter().map(|arg| unquote(&arg).to_string()),
+                None => { return Err(invalid()); }
+            };
+            let name = args.next().unwrap();
+            if name.len() == 0 {
+                return Err(self.diagnostic(ParseErrorKind::EmptyFilter, tag.to_string()));
+            }
+
+            filters.push(ast::Filter { name: name, args: args.collect() });
+        }
+
+        let name = try!(self.parse_call(&name, tag));
+        Ok(Tag { filters: filters, .. name })
+    }
+
+    /// Parses a name into its path and, if helpers are turned on, the
+    /// arguments that follow it, like the `amount "USD"` of
+    /// `format_money amount "USD"`.
+    fn parse_call(&self, name: &str, tag: &str) -> Result<Tag, Diagnostic> {
+        if !self.helpers {
+            return Ok(Tag::new(path(name)));
+        }
+
+        let words = match split_words(name) {
+            Some(words) => words,
+            None => {
+                return Err(self.diagnostic(ParseErrorKind::InvalidArgument, tag.to_string()));
+            }
+        };
+
+        let mut args = Vec::new();
+        for &(ref word, quoted) in words[1..].iter() {
+            args.push(if quoted {
+                ast::Argument::Str(word.clone())
+            } else {
+                match word.parse::<i64>() {
+                    Ok(n) => ast::Argument::Int(n),
+                    Err(_) => ast::Argument::Path(path(word)),
+                }
+            });
+        }
+
+        match words[0] {
+            (ref name, false) => Ok(Tag { path: path(name), args: args, filters: Vec::new() }),
+            // The name of the helper can't be a string.
+            (_, true) => Err(self.diagnostic(ParseErrorKind::InvalidArgument, tag.to_string())),
+        }
+    }
+
+    fn check_content(&self, content: &str, tag: &str) -> Result<String, Diagnostic> {
         let trimmed = StrExt::trim(content);
         if trimmed.len() == 0 {
-            panic!("empty tag");
+            return Err(self.diagnostic(ParseErrorKind::EmptyTag, tag.to_string()));
         }
-        trimmed.to_string()
+        Ok(trimmed.to_string())
+    }
+}
+
+/// Splits a dotted name into a path. The name "." is the current context,
+/// which is represented with an empty path.
+fn path(name: &str) -> Vec<String> {
+    if name == "." {
+        Vec::new()
+    } else {
+        name.split_terminator('.')
+            .map(|x| x.to_string())
+            .collect()
+    }
+}
+
+/// Splits `s` into words on whitespace, keeping quoted strings together.
+/// Each word is returned along with whether it was quoted, with the quotes
+/// removed. Returns `None` if a quote is never closed.
+fn split_words(s: &str) -> Option<Vec<(String, bool)>> {
+    let mut words = Vec::new();
+    let mut chars = s.chars();
+
+    loop {
+        let mut word = String::new();
+        let mut quoted = false;
+
+        // Skip the whitespace before the word.
+        let mut ch = chars.next();
+        while let Some(c) = ch {
+            if !c.is_whitespace() { break; }
+            ch = chars.next();
+        }
+
+        match ch {
+            None => { return Some(words); }
+            Some('"') => {
+                quoted = true;
+                loop {
+                    match chars.next() {
+                        None => { return None; }
+                        Some('"') => { break; }
+                        Some(c) => { word.push(c); }
+                    }
+                }
+            }
+            Some(c) => {
+                word.push(c);
+                loop {
+                    match chars.next() {
+                        None => { break; }
+                        Some(c) if c.is_whitespace() => { break; }
+                        Some(c) => { word.push(c); }
+                    }
+                }
+            }
+        }
+
+        words.push((word, quoted));
+    }
+}
+
+/// Splits `s` on `sep`, except where it is inside double quotes, and trims
+/// the whitespace around each part. The quotes are kept, so that the parts
+/// can be split again. Returns `None` if a quote is never closed.
+fn split_quoted(s: &str, sep: char) -> Option<Vec<String>> {
+    let mut parts = Vec::new();
+    let mut part = String::new();
+    let mut quoted = false;
+
+    for c in s.chars() {
+        if c == sep && !quoted {
+            parts.push(StrExt::trim(&part).to_string());
+            part = String::new();
+        } else {
+            if c == '"' { quoted = !quoted; }
+            part.push(c);
+        }
+    }
+
+    if quoted {
+        return None;
+    }
+
+    parts.push(StrExt::trim(&part).to_string());
+    Some(parts)
+}
+
+/// Strips the double quotes around a filter argument, so `"20"` and `20`
+/// are the same. Quoting lets an argument contain `:` or `|`, as in
+/// `truncate:20:" | ..."`.
+fn unquote(arg: &str) -> &str {
+    if arg.len() >= 2 && arg.starts_with("\"") && arg.ends_with("\"") {
+        arg.slice(1, arg.len() - 1)
+    } else {
+        arg
     }
 }
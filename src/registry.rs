@@ -0,0 +1,444 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::io::Write;
+use std::path::Path;
+use std::rc::Rc;
+use serialize::Encodable;
+
+use compiler::Compiler;
+use context::Context;
+use data::Data;
+use error::Error;
+use parser::Token;
+use template::{self, Template};
+
+/// `Registry` stores compiled templates by name. Each template and partial
+/// is only compiled once, and the compiled partials are shared between all
+/// the templates that use them.
+///
+/// With hot reloading turned on, a template is compiled again when it is
+/// rendered if its source, or that of any partial it uses, has changed.
+pub struct Registry {
+    ctx: Context,
+    hot_reload: bool,
+    state: RefCell<State>,
+}
+
+struct State {
+    templates: HashMap<String, Entry>,
+    partials: HashMap<String, Rc<Vec<Token>>>,
+    /// When the source of each compiled partial was modified, if known.
+    modified: HashMap<String, Option<u64>>,
+}
+
+struct Entry {
+    template: Rc<Template>,
+    /// The source of the template, if it wasn't loaded by name.
+    source: Option<String>,
+    /// The template and every partial it uses, and when they were modified
+    /// when the template was compiled.
+    dependencies: Vec<(String, Option<u64>)>,
+}
+
+impl Registry {
+    /// Creates an empty registry whose templates are loaded and compiled
+    /// with `ctx`.
+    pub fn new(ctx: Context) -> Registry {
+        Registry {
+            ctx: ctx,
+            hot_reload: false,
+            state: RefCell::new(State {
+                templates: HashMap::new(),
+                partials: HashMap::new(),
+                modified: HashMap::new(),
+            }),
+        }
+    }
+
+    /// Turns on hot reloading, which is useful during development.
+    pub fn with_hot_reload(self) -> Registry {
+        Registry {
+            hot_reload: true,
+            .. self
+        }
+    }
+
+    /// Loads the template `name` with the context's `PartialLoader`, and
+    /// registers it. Nothing is compiled if `name` was already compiled,
+    /// either as a template or as a partial of one.
+    pub fn register(&mut self, name: &str) -> Result<(), Error> {
+        let mut state = self.state.borrow_mut();
+
+        if state.partials.contains_key(name) {
+            state.add_template(&self.ctx, name, None);
+            Ok(())
+        } else {
+            let modified = self.ctx.partial_modified(name);
+            let src = try!(self.ctx.load_partial(name));
+            let partials = state.partials.clone();
+
+            state.compile(&self.ctx, name, &src, modified, partials, None)
+        }
+    }
+
+    /// Registers the template at `path`, relative to the context's template
+    /// path, the way `Context::compile_path` finds it. It is registered by
+    /// its path, without the template extension, so `page.mustache` and
+    /// `page` are both registered as `page`, and is reloaded when it changes
+    /// like any other template.
+    pub fn register_path(&mut self, path: &Path) -> Result<(), Error> {
+        let name = match path.to_str() {
+            Some(name) => name.to_string(),
+            None => { return Err(Error::TemplateNotFound(path.display().to_string())); }
+        };
+
+        let suffix = format!(".{}", self.ctx.template_extension);
+        let name = if name.ends_with(&suffix) {
+            name.slice_to(name.len() - suffix.len()).to_string()
+        } else {
+            name
+        };
+
+        self.register(&name)
+    }
+
+    /// Compiles `src` and registers it as the template `name`. The template
+    /// can also be used as a partial by the templates registered after it.
+    pub fn register_str(&mut self, name: &str, src: &str) -> Result<(), Error> {
+        let mut state = self.state.borrow_mut();
+        let partials = state.partials.clone();
+
+        state.compile(&self.ctx, name, src, None, partials, Some(src.to_string()))
+    }
+
+    /// Compiles the template `name` again if its source, or that of any
+    /// partial it uses, has changed since it was compiled. If it fails to
+    /// compile, the previous version is kept.
+    pub fn reload(&self, name: &str) -> Result<(), Error> {
+        let mut state = self.state.borrow_mut();
+
+        let (changed, source) = match state.templates.get(name) {
+            None => { return Err(Error::TemplateNotFound(name.to_string())); }
+            Some(entry) => {
+                // The partials the template loaded while rendering aren't
+                // known until then, so they are checked separately.
+                template::reload_dynamic_partials(&entry.template);
+
+                let changed = entry.dependencies.iter().filter(|&&(ref dependency, modified)| {
+                    self.ctx.partial_modified(dependency) != modified
+                }).map(|&(ref dependency, _)| dependency.clone()).collect::<Vec<String>>();
+
+                (changed, entry.source.clone())
+            }
+        };
+
+        if changed.is_empty() {
+            return Ok(());
+        }
+
+        // Any partials that changed need to be loaded again, along with the
+        // template itself.
+        let mut partials = state.partials.clone();
+        for dependency in changed.iter() {
+            partials.remove(dependency);
+        }
+        partials.remove(name);
+
+        let modified = self.ctx.partial_modified(name);
+        let src = match source {
+            Some(ref src) if !changed.iter().any(|dependency| *dependency == name) => src.clone(),
+            _ => try!(self.ctx.load_partial(name)),
+        };
+
+        state.compile(&self.ctx, name, &src, modified, partials, source)
+    }
+
+    /// Returns the template registered as `name`. With hot reloading, it is
+    /// compiled again first if it, or a partial it uses, has changed, and an
+    /// error is returned if that fails. The last good version stays
+    /// registered until the error is fixed.
+    pub fn get(&self, name: &str) -> Result<Option<Rc<Template>>, Error> {
+        if self.hot_reload && self.state.borrow().templates.contains_key(name) {
+            try!(self.reload(name));
+        }
+
+        Ok(self.state.borrow().templates.get(name).map(|entry| entry.template.clone()))
+    }
+
+    /// Renders the template registered as `name` with the `Encodable` data.
+    /// Unlike `Template::render`, the data comes before the writer, so that
+    /// the template and its data read together.
+    pub fn render<W: Write, T: Encodable>(
+        &self,
+        name: &str,
+        data: &T,
+        wr: &mut W
+    ) -> Result<(), Error> {
+        match try!(self.get(name)) {
+            Some(template) => template.render(wr, data),
+            None => Err(Error::TemplateNotFound(name.to_string())),
+        }
+    }
+
+    /// Renders the template registered as `name` with the `Data`.
+    pub fn render_data<W: Write>(
+        &self,
+        name: &str,
+        data: &Data,
+        wr: &mut W
+    ) -> Result<(), Error> {
+        match try!(self.get(name)) {
+            Some(template) => template.render_data(wr, data),
+            None => Err(Error::TemplateNotFound(name.to_string())),
+        }
+    }
+}
+
+impl State {
+    /// Compiles `src` as the template `name`, on top of the already compiled
+    /// `partials`. Nothing changes if it fails to compile.
+    fn compile(
+        &mut self,
+        ctx: &Context,
+        name: &str,
+        src: &str,
+        modified: Option<u64>,
+        partials: HashMap<String, Rc<Vec<Token>>>,
+        source: Option<String>
+    ) -> Result<(), Error> {
+        let compiler = Compiler::new_with(
+            ctx.clone(),
+            src.chars(),
+            partials.clone(),
+            "{{".to_string(),
+            "}}".to_string());
+
+        let (tokens, compiled) = try!(compiler.compile());
+
+        // Remember when the partials the compiler loaded were modified.
+        for partial in compiled.keys() {
+            if !partials.contains_key(partial) {
+                self.modified.insert(partial.clone(), ctx.partial_modified(partial));
+            }
+        }
+
+        self.partials = compiled;
+        self.partials.insert(name.to_string(), Rc::new(tokens));
+        self.modified.insert(name.to_string(), modified);
+        self.add_template(ctx, name, source);
+
+        Ok(())
+    }
+
+    /// Registers the compiled partial `name` as a template.
+    fn add_template(&mut self, ctx: &Context, name: &str, source: Option<String>) {
+        let tokens = self.partials.get(name).unwrap().clone();
+
+        let mut names = vec!(name.to_string());
+        dependencies(&tokens, &self.partials, &mut names);
+
+        let dependencies = names.into_iter().map(|name| {
+            let modified = match self.modified.get(&name) {
+                Some(&modified) => modified,
+                None => None,
+            };
+            (name, modified)
+        }).collect();
+
+        let template = template::new(ctx.for_template(name), tokens, self.partials.clone());
+
+        self.templates.insert(name.to_string(), Entry {
+            template: Rc::new(template),
+            source: source,
+            dependencies: dependencies,
+        });
+    }
+}
+
+/// Collects the names of all the partials used by `tokens`, including those
+/// used by the partials themselves.
+fn dependencies(
+    tokens: &[Token],
+    partials: &HashMap<String, Rc<Vec<Token>>>,
+    names: &mut Vec<String>
+) {
+    for token in tokens.iter() {
+        match *token {
+            Token::Partial(ref name, _, _, _) | Token::Parent(ref name, _, _, _, _, _, _) => {
+                if !names.contains(name) {
+                    names.push(name.clone());
+
+                    if let Some(tokens) = partials.get(name) {
+                        dependencies(&tokens, partials, names);
+                    }
+                }
+            }
+            _ => { }
+        }
+
+        match *token {
+            Token::Section(_, _, ref children, _, _, _, _, _, _)
+            | Token::Parent(_, _, ref children, _, _, _, _)
+            | Token::Block(_, _, ref children, _, _, _, _) => {
+                dependencies(&children, partials, names);
+            }
+            _ => { }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::collections::HashMap;
+    use std::path::Path;
+    use std::rc::Rc;
+
+    use context::Context;
+    use data::Data;
+    use error::Error;
+    use loader::PartialLoader;
+    use map::Map;
+    use super::Registry;
+
+    fn render(registry: &Registry, name: &str, data: &Data) -> Result<String, Error> {
+        let mut bytes = Vec::new();
+        try!(registry.render_data(name, data, &mut bytes));
+        Ok(String::from_utf8(bytes).unwrap())
+    }
+
+    /// A loader whose partials can be changed while it is in use.
+    #[derive(Clone)]
+    struct Partials {
+        partials: Rc<RefCell<HashMap<String, (String, u64)>>>,
+    }
+
+    impl Partials {
+        fn set(&self, name: &str, src: &str, modified: u64) {
+            self.partials.borrow_mut().insert(name.to_string(), (src.to_string(), modified));
+        }
+    }
+
+    impl PartialLoader for Partials {
+        fn load(&self, name: &str) -> Result<String, Error> {
+            match self.partials.borrow().get(name) {
+                Some(&(ref src, _)) => Ok(src.clone()),
+                None => Err(Error::PartialNotFound(name.to_string())),
+            }
+        }
+
+        fn modified(&self, name: &str) -> Option<u64> {
+            self.partials.borrow().get(name).map(|&(_, modified)| modified)
+        }
+    }
+
+    #[test]
+    fn test_registry() {
+        let mut registry = Registry::new(Context::new(Path::new("src/test-data")));
+        registry.register("base").unwrap();
+
+        // "user" is compiled along with "base", so registering it reuses it.
+        let tokens = &**registry.state.borrow().partials.get("user").unwrap() as *const _;
+        registry.register("user").unwrap();
+        assert_eq!(&**registry.state.borrow().partials.get("user").unwrap() as *const _, tokens);
+
+        let mut ctx = Map::new();
+        ctx.insert("name".to_string(), Data::Str("a".to_string()));
+        let data = Data::Map(ctx);
+
+        assert_eq!(render(&registry, "user", &data), Ok("<strong>a</strong>\n".to_string()));
+    }
+
+    #[test]
+    fn test_register_str() {
+        let mut registry = Registry::new(Context::new(Path::new("src/test-data")));
+        registry.register_str("greeting", "hello {{name}}").unwrap();
+        registry.register_str("page", "{{>greeting}}!").unwrap();
+
+        let mut ctx = Map::new();
+        ctx.insert("name".to_string(), Data::Str("world".to_string()));
+
+        assert_eq!(render(&registry, "page", &Data::Map(ctx)), Ok("hello world!".to_string()));
+
+        let mut data = HashMap::new();
+        data.insert("name", "you");
+
+        let mut bytes = Vec::new();
+        registry.render("page", &data, &mut bytes).unwrap();
+        assert_eq!(String::from_utf8(bytes).unwrap(), "hello you!".to_string());
+    }
+
+    #[test]
+    fn test_template_not_found() {
+        let registry = Registry::new(Context::new(Path::new("src/test-data")));
+
+        assert_eq!(
+            render(&registry, "missing", &Data::Map(Map::new())),
+            Err(Error::TemplateNotFound("missing".to_string())));
+    }
+
+    #[test]
+    fn test_hot_reload() {
+        let partials = Partials { partials: Rc::new(RefCell::new(HashMap::new())) };
+        partials.set("page", "{{>header}}!", 1);
+        partials.set("header", "a", 1);
+
+        let ctx = Context::new(Path::new(".")).with_loader(partials.clone());
+        let mut registry = Registry::new(ctx).with_hot_reload();
+        registry.register("page").unwrap();
+
+        let data = Data::Map(Map::new());
+        assert_eq!(render(&registry, "page", &data), Ok("a!".to_string()));
+
+        partials.set("header", "b", 2);
+        assert_eq!(render(&registry, "page", &data), Ok("b!".to_string()));
+
+        // A template that no longer compiles is an error, but keeps its last
+        // good version.
+        partials.set("header", "{{b", 3);
+        assert!(registry.reload("page").is_err());
+        assert!(registry.get("page").is_err());
+        assert!(render(&registry, "page", &data).is_err());
+        assert_eq!(
+            registry.state.borrow().templates["page"].template.render_data_to_string(&data),
+            Ok("b!".to_string()));
+
+        partials.set("page", "{{>header}}?", 4);
+        partials.set("header", "c", 4);
+        assert_eq!(render(&registry, "page", &data), Ok("c?".to_string()));
+    }
+
+    #[test]
+    fn test_hot_reload_dynamic_partials() {
+        let partials = Partials { partials: Rc::new(RefCell::new(HashMap::new())) };
+        partials.set("page", "{{>*kind}}!", 1);
+        partials.set("header", "a", 1);
+
+        let ctx = Context::new(Path::new(".")).with_loader(partials.clone());
+        let mut registry = Registry::new(ctx).with_hot_reload();
+        registry.register("page").unwrap();
+
+        let mut ctx = Map::new();
+        ctx.insert("kind".to_string(), Data::Str("header".to_string()));
+        let data = Data::Map(ctx);
+        assert_eq!(render(&registry, "page", &data), Ok("a!".to_string()));
+
+        partials.set("header", "b", 2);
+        assert_eq!(render(&registry, "page", &data), Ok("b!".to_string()));
+    }
+
+    #[test]
+    fn test_register_path() {
+        let mut registry = Registry::new(Context::new(Path::new("src/test-data")));
+        registry.register_path(Path::new("user.mustache")).unwrap();
+        registry.register_path(Path::new("base")).unwrap();
+
+        let mut ctx = Map::new();
+        ctx.insert("name".to_string(), Data::Str("a".to_string()));
+        let data = Data::Map(ctx);
+
+        assert_eq!(render(&registry, "user", &data), Ok("<strong>a</strong>\n".to_string()));
+        assert!(registry.get("base").unwrap().is_some());
+        assert!(registry.get("missing").unwrap().is_none());
+    }
+}
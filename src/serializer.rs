@@ -0,0 +1,221 @@
+use serde::ser::{self, Serialize};
+
+use data::Data;
+use error::Error;
+use map::Map;
+
+/// `Serializer` converts serde `Serialize` values to `Data`, in the same way
+/// that `Encoder` converts `Encodable` ones.
+pub struct Serializer {
+    pub data: Vec<Data>,
+}
+
+impl Serializer {
+    pub fn new() -> Serializer {
+        Serializer { data: Vec::new() }
+    }
+
+    fn push(&mut self, data: Data) -> Result<(), Error> {
+        self.data.push(data);
+        Ok(())
+    }
+
+    /// Pushes a map from the variant `name` to the values serialized by
+    /// `visitor`, like `Encoder::emit_enum_variant`.
+    fn visit_variant<V: ser::SeqVisitor>(&mut self, name: &str, visitor: V) -> Result<(), Error> {
+        try!(ser::Serializer::visit_seq(self, visitor));
+        let value = match self.data.pop() {
+            Some(Data::Vec(mut v)) => {
+                if v.len() == 1 {
+                    v.pop().unwrap()
+                } else {
+                    Data::Vec(v)
+                }
+            }
+            _ => { return Err(Error::UnsupportedType); }
+        };
+        self.push(variant_map(name, value))
+    }
+}
+
+impl ser::Serializer for Serializer {
+    type Error = Error;
+
+    fn visit_bool(&mut self, v: bool) -> Result<(), Error> { self.push(Data::Bool(v)) }
+
+    fn visit_isize(&mut self, v: isize) -> Result<(), Error> { self.visit_i64(v as i64) }
+    fn visit_i8(&mut self, v: i8) -> Result<(), Error>       { self.visit_i64(v as i64) }
+    fn visit_i16(&mut self, v: i16) -> Result<(), Error>     { self.visit_i64(v as i64) }
+    fn visit_i32(&mut self, v: i32) -> Result<(), Error>     { self.visit_i64(v as i64) }
+    fn visit_i64(&mut self, v: i64) -> Result<(), Error>     { self.push(Data::Int(v)) }
+
+    fn visit_usize(&mut self, v: usize) -> Result<(), Error> { self.visit_u64(v as u64) }
+    fn visit_u8(&mut self, v: u8) -> Result<(), Error>       { self.visit_u64(v as u64) }
+    fn visit_u16(&mut self, v: u16) -> Result<(), Error>     { self.visit_u64(v as u64) }
+    fn visit_u32(&mut self, v: u32) -> Result<(), Error>     { self.visit_u64(v as u64) }
+    fn visit_u64(&mut self, v: u64) -> Result<(), Error>     { self.push(Data::UInt(v)) }
+
+    fn visit_f32(&mut self, v: f32) -> Result<(), Error> { self.visit_f64(v as f64) }
+    fn visit_f64(&mut self, v: f64) -> Result<(), Error> { self.push(Data::Float(v)) }
+
+    fn visit_char(&mut self, v: char) -> Result<(), Error> {
+        let mut text = String::with_capacity(1);
+        text.push(v);
+        self.push(Data::Str(text))
+    }
+
+    fn visit_str(&mut self, v: &str) -> Result<(), Error> { self.push(Data::Str(v.to_string())) }
+
+    fn visit_unit(&mut self) -> Result<(), Error> { self.push(Data::Null) }
+
+    // Like the `Encoder`, variants without arguments are serialized as their
+    // name, and the others as a map from their name to their arguments.
+    fn visit_enum_unit(&mut self, _name: &str, variant: &str) -> Result<(), Error> {
+        self.push(Data::Str(variant.to_string()))
+    }
+
+    // `None` is null, which is falsey, and `Some` is just its value.
+    fn visit_none(&mut self) -> Result<(), Error> { self.push(Data::Null) }
+
+    fn visit_some<V: Serialize>(&mut self, value: V) -> Result<(), Error> {
+        value.serialize(self)
+    }
+
+    fn visit_seq<V: ser::SeqVisitor>(&mut self, mut visitor: V) -> Result<(), Error> {
+        try!(self.push(Data::Vec(Vec::new())));
+        while let Some(()) = try!(visitor.visit(self)) { }
+        Ok(())
+    }
+
+    fn visit_enum_seq<V: ser::SeqVisitor>(
+        &mut self,
+        _name: &str,
+        variant: &str,
+        visitor: V
+    ) -> Result<(), Error> {
+        self.visit_variant(variant, visitor)
+    }
+
+    fn visit_seq_elt<T: Serialize>(&mut self, value: T) -> Result<(), Error> {
+        let value = try!(serialize(&value));
+        match self.data.last_mut() {
+            Some(&mut Data::Vec(ref mut v)) => { v.push(value); }
+            _ => { return Err(Error::UnsupportedType); }
+        }
+        Ok(())
+    }
+
+    fn visit_map<V: ser::MapVisitor>(&mut self, mut visitor: V) -> Result<(), Error> {
+        try!(self.push(Data::Map(Map::new())));
+        while let Some(()) = try!(visitor.visit(self)) { }
+        Ok(())
+    }
+
+    fn visit_enum_map<V: ser::MapVisitor>(
+        &mut self,
+        _name: &str,
+        variant: &str,
+        visitor: V
+    ) -> Result<(), Error> {
+        try!(self.visit_map(visitor));
+        let value = match self.data.pop() {
+            Some(value) => value,
+            None => { return Err(Error::MissingElements); }
+        };
+        self.push(variant_map(variant, value))
+    }
+
+    fn visit_map_elt<K: Serialize, V: Serialize>(&mut self, key: K, value: V) -> Result<(), Error> {
+        // Integer keys are used as strings, just as the `Encoder` does.
+        let key = match try!(serialize(&key)) {
+            Data::Str(s) => s,
+            Data::Int(v) => v.to_string(),
+            Data::UInt(v) => v.to_string(),
+            _ => { return Err(Error::KeyIsNotString); }
+        };
+        let value = try!(serialize(&value));
+
+        match self.data.last_mut() {
+            Some(&mut Data::Map(ref mut m)) => { m.insert(key, value); }
+            _ => { return Err(Error::UnsupportedType); }
+        }
+        Ok(())
+    }
+}
+
+/// Returns a map from the variant `name` to its `value`.
+fn variant_map(name: &str, value: Data) -> Data {
+    let mut m = Map::new();
+    m.insert(name.to_string(), value);
+    Data::Map(m)
+}
+
+/// Converts the `Serialize` data to `Data`.
+pub fn serialize<T: Serialize>(data: &T) -> Result<Data, Error> {
+    let mut serializer = Serializer::new();
+    try!(data.serialize(&mut serializer));
+    match serializer.data.pop() {
+        Some(data) => Ok(data),
+        None => Err(Error::MissingElements),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use builder::MapBuilder;
+    use data::Data;
+    use error::Error;
+    use map::Map;
+    use super::serialize;
+    use super::super::compile_str;
+
+    #[test]
+    fn test_serialize() {
+        assert_eq!(serialize(&"a"), Ok(Data::Str("a".to_string())));
+        assert_eq!(serialize(&-1i32), Ok(Data::Int(-1)));
+        assert_eq!(serialize(&1u8), Ok(Data::UInt(1)));
+        assert_eq!(serialize(&0.5f32), Ok(Data::Float(0.5)));
+        assert_eq!(serialize(&None::<u32>), Ok(Data::Null));
+        assert_eq!(serialize(&Some(true)), Ok(Data::Bool(true)));
+        assert_eq!(
+            serialize(&("a", vec!(1u32))),
+            Ok(Data::Vec(vec!(Data::Str("a".to_string()), Data::Vec(vec!(Data::UInt(1)))))));
+
+        let mut m = HashMap::new();
+        m.insert(1u32, "a");
+        let mut expected = Map::new();
+        expected.insert("1".to_string(), Data::Str("a".to_string()));
+        assert_eq!(serialize(&m), Ok(Data::Map(expected)));
+
+        let mut m = HashMap::new();
+        m.insert(true, "a");
+        assert_eq!(serialize(&m), Err(Error::KeyIsNotString));
+    }
+
+    #[test]
+    fn test_render_serde() {
+        let mut m = HashMap::new();
+        m.insert("name", vec!("a", "b"));
+
+        let template = compile_str("{{#name}}<{{.}}>{{/name}}").unwrap();
+        let mut bytes = Vec::new();
+        template.render_serde(&mut bytes, &m).unwrap();
+        assert_eq!(String::from_utf8(bytes).unwrap(), "<a><b>".to_string());
+    }
+
+    #[test]
+    fn test_serde_builders() {
+        let mut m = Map::new();
+        m.insert("count".to_string(), Data::UInt(2));
+        m.insert("names".to_string(), Data::Vec(vec!(Data::Null)));
+
+        assert_eq!(
+            MapBuilder::new()
+                .insert_serde("count".to_string(), &2u32).unwrap()
+                .insert_vec("names".to_string(), |builder| builder.push_serde(&()).unwrap())
+                .build(),
+            Data::Map(m));
+    }
+}
@@ -1,36 +1,68 @@
-use std::cell::RefCell;
+use std::cell::{RefCell, RefMut};
 use std::collections::HashMap;
+use std::error::FromError;
+use std::fmt;
 use std::fs::File;
-use std::io::Write;
+use std::io::{self, Write};
 use std::mem;
-use std::str;
+use std::rc::Rc;
 use serialize::Encodable;
 
+#[cfg(feature = "serde")]
+use serde::Serialize;
+
+use ast;
 use compiler::Compiler;
 use data::Data;
 use encoder::Encoder;
 use encoder;
 use error::Error;
-use parser::Token;
+use escape::Escaper;
+use helper::Helper;
+use parser::{Span, Token};
 use parser;
+#[cfg(feature = "serde")]
+use serializer;
 use context::Context;
 
 /// `Template` represents a compiled mustache file.
 #[derive(Debug, Clone)]
 pub struct Template {
     ctx: Context,
-    tokens: Vec<Token>,
-    partials: HashMap<String, Vec<Token>>
+    tokens: Rc<Vec<Token>>,
+    partials: HashMap<String, Rc<Vec<Token>>>,
+    /// The partials loaded while rendering, and when they were modified.
+    dynamic_partials: RefCell<HashMap<String, (Rc<Vec<Token>>, Option<u64>)>>,
 }
 
 /// Construct a `Template`. This is not part of the impl of Template so it is
-/// not exported outside of mustache.
-pub fn new(ctx: Context, tokens: Vec<Token>, partials: HashMap<String,
-Vec<Token>>) -> Template {
+/// not exported outside of mustache. The tokens are shared with any other
+/// templates that use them as a partial.
+pub fn new(ctx: Context, tokens: Rc<Vec<Token>>, partials: HashMap<String,
+Rc<Vec<Token>>>) -> Template {
     Template {
         ctx: ctx,
         tokens: tokens,
         partials: partials,
+        dynamic_partials: RefCell::new(HashMap::new()),
+    }
+}
+
+/// Forgets the partials loaded while rendering `template` if any of their
+/// sources have changed since, so that they are loaded again the next time
+/// they are used.
+pub fn reload_dynamic_partials(template: &Template) {
+    let ctx = &template.ctx;
+    let mut dynamic_partials = template.dynamic_partials.borrow_mut();
+
+    let changed = dynamic_partials.iter().any(|(name, &(_, modified))| {
+        ctx.partial_modified(name) != modified
+    });
+
+    // A partial that changed may have used other partials that it no longer
+    // does, so they are all loaded again.
+    if changed {
+        dynamic_partials.clear();
     }
 }
 
@@ -42,89 +74,358 @@ impl Template {
         data: &T
     ) -> Result<(), Error> {
         let data = try!(encoder::encode(data));
-        Ok(self.render_data(wr, &data))
+        self.render_data(wr, &data)
+    }
+
+    /// Renders the template with the serde `Serialize` data.
+    #[cfg(feature = "serde")]
+    pub fn render_serde<W: Write, T: Serialize>(&self, wr: &mut W, data: &T) -> Result<(), Error> {
+        let data = try!(serializer::serialize(data));
+        self.render_data(wr, &data)
+    }
+
+    /// Renders the template with the `Encodable` data to a `fmt::Write`.
+    pub fn render_fmt<'a, W: fmt::Write, T: Encodable>(
+        &self,
+        wr: &mut W,
+        data: &T
+    ) -> Result<(), Error> {
+        let data = try!(encoder::encode(data));
+        self.render_data_fmt(wr, &data)
+    }
+
+    /// Renders the template with the `Encodable` data to a `String`.
+    pub fn render_to_string<'a, T: Encodable>(&self, data: &T) -> Result<String, Error> {
+        let mut s = String::new();
+        try!(self.render_fmt(&mut s, data));
+        Ok(s)
+    }
+
+    /// Makes rendering fail with `Error::MissingVariable` when a variable or
+    /// section isn't found in the data, like `Context::with_strict`, but for
+    /// this template only.
+    pub fn with_strict(self) -> Template {
+        Template {
+            ctx: self.ctx.with_strict(),
+            .. self
+        }
+    }
+
+    /// Returns whether missing variables and sections are errors.
+    pub fn is_strict(&self) -> bool {
+        self.ctx.is_strict()
+    }
+
+    /// Returns the syntax tree of the template.
+    pub fn ast(&self) -> Vec<ast::Node> {
+        parser::to_nodes(&self.tokens)
+    }
+
+    /// Returns a `Renderer`, which renders the template with options that
+    /// apply to a single render, like `on_missing`.
+    pub fn renderer(&self) -> Renderer {
+        Renderer { template: self, on_missing: None }
     }
 
     /// Renders the template with the `Data`.
-    pub fn render_data<W: Write>(&self, wr: &mut W, data: &Data) {
-        let mut render_ctx = RenderContext::new(self);
+    pub fn render_data<W: Write>(&self, wr: &mut W, data: &Data) -> Result<(), Error> {
+        self.render_io(wr, data, None)
+    }
+
+    /// Renders the template with the `Data` to a `fmt::Write`.
+    pub fn render_data_fmt<W: fmt::Write>(&self, wr: &mut W, data: &Data) -> Result<(), Error> {
+        self.render_with(wr, data, None)
+    }
+
+    /// Renders the template with the `Data` to a `String`.
+    pub fn render_data_to_string(&self, data: &Data) -> Result<String, Error> {
+        let mut s = String::new();
+        try!(self.render_data_fmt(&mut s, data));
+        Ok(s)
+    }
+
+    fn render_io<W: Write>(
+        &self,
+        wr: &mut W,
+        data: &Data,
+        on_missing: Option<&mut FnMut(&[String]) -> Option<Data>>
+    ) -> Result<(), Error> {
+        let mut wr = IoWriter { wr: wr, error: None };
+        let result = self.render_with(&mut wr, data, on_missing);
+
+        // Report the I/O error that caused the formatting error.
+        match wr.error {
+            Some(err) => Err(FromError::from_error(err)),
+            None => result,
+        }
+    }
+
+    fn render_with(
+        &self,
+        wr: &mut fmt::Write,
+        data: &Data,
+        on_missing: Option<&mut FnMut(&[String]) -> Option<Data>>
+    ) -> Result<(), Error> {
+        let mut render_ctx = RenderContext::new(self, on_missing);
         let mut stack = vec!(data);
 
         render_ctx.render(
             wr,
             &mut stack,
-            &self.tokens);
+            &self.tokens)
+    }
+
+    /// Loads a partial that wasn't known when the template was compiled,
+    /// such as one named by the data. Each partial is only loaded once.
+    /// Only partials that load and compile are kept, so names that don't
+    /// exist can't fill up the cache.
+    fn load_partial(&self, name: &str) -> Result<Option<Rc<Vec<Token>>>, Error> {
+        if let Some(&(ref tokens, _)) = self.dynamic_partials.borrow().get(name) {
+            return Ok(Some(tokens.clone()));
+        }
+
+        // A missing partial renders as nothing, just like a missing value,
+        // unless in strict mode.
+        let src = match self.ctx.load_partial(name) {
+            Ok(src) => src,
+            Err(Error::PartialNotFound(_)) if !self.ctx.is_strict() => { return Ok(None); }
+            Err(err) => { return Err(err); }
+        };
+
+        let mut partials = self.partials.clone();
+        for (partial, &(ref partial_tokens, _)) in self.dynamic_partials.borrow().iter() {
+            partials.insert(partial.clone(), partial_tokens.clone());
+        }
+
+        let compiler = Compiler::new_with(
+            self.ctx.clone(),
+            src.chars(),
+            partials,
+            "{{".to_string(),
+            "}}".to_string());
+
+        let (tokens, partials) = try!(compiler.compile());
+
+        let tokens = Rc::new(tokens);
+
+        // Keep any partials the partial used as well.
+        let mut dynamic_partials = self.dynamic_partials.borrow_mut();
+        for (partial, partial_tokens) in partials.into_iter() {
+            if !self.partials.contains_key(&partial) && !dynamic_partials.contains_key(&partial) {
+                let modified = self.ctx.partial_modified(&partial);
+                dynamic_partials.insert(partial, (partial_tokens, modified));
+            }
+        }
+        let modified = self.ctx.partial_modified(name);
+        dynamic_partials.insert(name.to_string(), (tokens.clone(), modified));
+
+        Ok(Some(tokens))
+    }
+}
+
+/// `Renderer` renders a template with options that apply to a single render.
+/// It is made with `Template::renderer`, as in
+/// `template.renderer().on_missing(f).render_data(wr, &data)`.
+pub struct Renderer<'a> {
+    template: &'a Template,
+    on_missing: Option<Box<FnMut(&[String]) -> Option<Data> + 'a>>,
+}
+
+impl<'a> Renderer<'a> {
+    /// Calls `on_missing` with the path of each variable or section that
+    /// isn't found in the data. If it returns a value, that is rendered in
+    /// its place, even in strict mode.
+    pub fn on_missing<F>(self, on_missing: F) -> Renderer<'a>
+        where F: FnMut(&[String]) -> Option<Data> + 'a
+    {
+        Renderer {
+            on_missing: Some(Box::new(on_missing) as Box<FnMut(&[String]) -> Option<Data>>),
+            .. self
+        }
+    }
+
+    /// Renders the template with the `Encodable` data.
+    pub fn render<W: Write, T: Encodable>(&mut self, wr: &mut W, data: &T) -> Result<(), Error> {
+        let data = try!(encoder::encode(data));
+        self.render_data(wr, &data)
+    }
+
+    /// Renders the template with the `Encodable` data to a `fmt::Write`.
+    pub fn render_fmt<W: fmt::Write, T: Encodable>(
+        &mut self,
+        wr: &mut W,
+        data: &T
+    ) -> Result<(), Error> {
+        let data = try!(encoder::encode(data));
+        self.render_data_fmt(wr, &data)
+    }
+
+    /// Renders the template with the `Encodable` data to a `String`.
+    pub fn render_to_string<T: Encodable>(&mut self, data: &T) -> Result<String, Error> {
+        let mut s = String::new();
+        try!(self.render_fmt(&mut s, data));
+        Ok(s)
+    }
+
+    /// Renders the template with the `Data`.
+    pub fn render_data<W: Write>(&mut self, wr: &mut W, data: &Data) -> Result<(), Error> {
+        let on_missing = self.on_missing.as_mut()
+            .map(|f| &mut **f as &mut FnMut(&[String]) -> Option<Data>);
+        self.template.render_io(wr, data, on_missing)
+    }
+
+    /// Renders the template with the `Data` to a `fmt::Write`.
+    pub fn render_data_fmt<W: fmt::Write>(
+        &mut self,
+        wr: &mut W,
+        data: &Data
+    ) -> Result<(), Error> {
+        let on_missing = self.on_missing.as_mut()
+            .map(|f| &mut **f as &mut FnMut(&[String]) -> Option<Data>);
+        self.template.render_with(wr, data, on_missing)
+    }
+
+    /// Renders the template with the `Data` to a `String`.
+    pub fn render_data_to_string(&mut self, data: &Data) -> Result<String, Error> {
+        let mut s = String::new();
+        try!(self.render_data_fmt(&mut s, data));
+        Ok(s)
+    }
+}
+
+/// `IoWriter` lets an `io::Write` be rendered to. A `fmt::Error` can't say
+/// what went wrong, so the `io::Error` is kept to be reported instead.
+struct IoWriter<'a, W: 'a> {
+    wr: &'a mut W,
+    error: Option<io::Error>,
+}
+
+impl<'a, W: Write> fmt::Write for IoWriter<'a, W> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        match self.wr.write_all(s.as_bytes()) {
+            Ok(()) => Ok(()),
+            Err(err) => {
+                self.error = Some(err);
+                Err(fmt::Error)
+            }
+        }
+    }
+}
+
+/// `Escaped` escapes everything written to it with an `Escaper`.
+struct Escaped<'a> {
+    wr: &'a mut (fmt::Write + 'a),
+    escaper: &'a (Escaper + 'a),
+}
+
+impl<'a> fmt::Write for Escaped<'a> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.escaper.escape(s, &mut *self.wr)
     }
 }
 
 struct RenderContext<'a> {
     template: &'a Template,
     indent: String,
+    blocks: HashMap<String, Vec<Token>>,
+    escaper: &'a (Escaper + 'a),
+    on_missing: Option<&'a mut (FnMut(&[String]) -> Option<Data> + 'a)>,
+    /// The index and length of each list being rendered, innermost last.
+    loops: Vec<(usize, usize)>,
 }
 
 impl<'a> RenderContext<'a> {
-    fn new(template: &'a Template) -> RenderContext<'a> {
+    fn new(
+        template: &'a Template,
+        on_missing: Option<&'a mut (FnMut(&[String]) -> Option<Data> + 'a)>
+    ) -> RenderContext<'a> {
         RenderContext {
             template: template,
             indent: "".to_string(),
+            blocks: HashMap::new(),
+            escaper: template.ctx.escaper(),
+            on_missing: on_missing,
+            loops: Vec::new(),
         }
     }
 
-    fn render<'b, W: Write>(
+    fn render(
         &mut self,
-        wr: &mut W,
+        wr: &mut fmt::Write,
         stack: &mut Vec<&Data>,
         tokens: &[Token]
-    ) {
+    ) -> Result<(), Error> {
         for token in tokens.iter() {
-            self.render_token(wr, stack, token);
+            try!(self.render_token(wr, stack, token));
         }
+
+        Ok(())
     }
 
-    fn render_token<'b, W: Write>(
+    fn render_token(
         &mut self,
-        wr: &mut W,
+        wr: &mut fmt::Write,
         stack: &mut Vec<&Data>,
         token: &Token
-    ) {
+    ) -> Result<(), Error> {
         match *token {
-            Token::Text(ref value) => {
-                self.render_text(wr, &value);
+            Token::Text(ref value, _) => {
+                self.render_text(wr, &value)
             },
-            Token::ETag(ref path, _) => {
-                self.render_etag(wr, stack, &path);
+            Token::ETag(ref name, _, span) => {
+                self.render_etag(wr, stack, &name.path, &name.args, &name.filters, span)
             }
-            Token::UTag(ref path, _) => {
-                self.render_utag(wr, stack, &path);
+            Token::UTag(ref name, _, span) => {
+                self.render_utag(wr, stack, &name.path, &name.args, &name.filters, span)
             }
-            Token::Section(ref path, true, ref children, _, _, _, _, _) => {
-                self.render_inverted_section(wr, stack, &path, &children);
+            Token::Section(ref name, true, ref children, _, _, _, _, _, span) => {
+                self.render_inverted_section(wr, stack, &name.path, &children, span)
             }
-            Token::Section(ref path, false, ref children, ref otag, _, ref src, _, ref ctag) => {
+            Token::Section(ref name,
+                           false,
+                           ref children,
+                           ref otag,
+                           _,
+                           ref src,
+                           _,
+                           ref ctag,
+                           span) => {
                 self.render_section(
                     wr,
                     stack,
-                    path,
+                    &name.path,
+                    &name.args,
                     children,
                     src,
                     otag,
-                    ctag)
+                    ctag,
+                    span)
+            }
+            Token::Partial(ref name, ref indent, _, _) => {
+                self.render_partial(wr, stack, &name, &indent)
             }
-            Token::Partial(ref name, ref indent, _) => {
-                self.render_partial(wr, stack, &name, &indent);
+            Token::DynamicPartial(ref path, ref indent, _, _) => {
+                self.render_dynamic_partial(wr, stack, &path, &indent)
             }
+            Token::Parent(ref name, ref indent, ref children, _, _, _, _) => {
+                self.render_parent(wr, stack, &name, &indent, &children)
+            }
+            Token::Block(ref name, ref indent, ref children, _, _, _, _) => {
+                self.render_block(wr, stack, &name, &indent, &children)
+            }
+            Token::Comment(..) | Token::Delimiters(..) => { Ok(()) }
             _ => { panic!() }
         }
     }
 
-    fn render_text<W: Write>(
+    fn render_text(
         &mut self,
-        wr: &mut W,
+        wr: &mut fmt::Write,
         value: &str
-    ) {
+    ) -> Result<(), Error> {
         // Indent the lines.
         if self.indent.is_empty() {
-            wr.write(value.as_bytes()).unwrap();
+            try!(wr.write_str(value));
         } else {
             let mut pos = 0;
             let len = value.len();
@@ -145,142 +446,418 @@ impl<'a> RenderContext<'a> {
                 };
 
                 if line.char_at(0) != '\n' {
-                    wr.write(self.indent.as_bytes()).unwrap();
+                    try!(wr.write_str(&self.indent));
                 }
 
-                wr.write(line.as_bytes()).unwrap();
+                try!(wr.write_str(line));
             }
         }
+
+        Ok(())
     }
 
-    fn render_etag<'b, W: Write>(
+    fn render_etag(
         &mut self,
-        wr: &mut W,
+        wr: &mut fmt::Write,
         stack: &mut Vec<&Data>,
-        path: &[String]
-    ) {
-        let mut bytes = Vec::new();
-
-        self.render_utag(&mut bytes, stack, path);
-
-        let s = str::from_utf8(&bytes).unwrap().to_string();
-
-        for c in s.chars() {
-            match c {
-                '<'  => { wr.write("&lt;".as_bytes()) }
-                '>'  => { wr.write("&gt;".as_bytes()) }
-                '&'  => { wr.write("&amp;".as_bytes()) }
-                '"'  => { wr.write("&quot;".as_bytes()) }
-                '\'' => { wr.write("&#39;".as_bytes()) }
-                _    => {
-                    let mut text:Vec<u8> = (0..c.len_utf8()).map(|_| 0).collect();
-                    c.encode_utf8(&mut text);
-                    wr.write(&text)
-                }
-            }.unwrap();
+        path: &[String],
+        args: &[ast::Argument],
+        filters: &[ast::Filter],
+        span: Span
+    ) -> Result<(), Error> {
+        // The indent is whitespace from the template, so it isn't escaped.
+        try!(wr.write_str(&self.indent));
+
+        let escaper = self.escaper;
+        try!(escaper.begin(wr));
+        {
+            let mut wr = Escaped { wr: &mut *wr, escaper: escaper };
+            try!(self.render_variable(&mut wr, stack, path, args, filters, span));
         }
+        try!(escaper.end(wr));
+
+        Ok(())
     }
 
-    fn render_utag<'b, W: Write>(
+    fn render_utag(
         &mut self,
-        wr: &mut W,
+        wr: &mut fmt::Write,
+        stack: &mut Vec<&Data>,
+        path: &[String],
+        args: &[ast::Argument],
+        filters: &[ast::Filter],
+        span: Span
+    ) -> Result<(), Error> {
+        try!(wr.write_str(&self.indent));
+
+        self.render_variable(wr, stack, path, args, filters, span)
+    }
+
+    /// Renders the value of a variable tag, once its indent is written.
+    fn render_variable(
+        &mut self,
+        wr: &mut fmt::Write,
         stack: &mut Vec<&Data>,
-        path: &[String]
-    ) {
+        path: &[String],
+        args: &[ast::Argument],
+        filters: &[ast::Filter],
+        span: Span
+    ) -> Result<(), Error> {
+        // etags and utags use the default delimiter.
+        if let Some(helper) = try!(self.helper(path, args)) {
+            let value = try!(self.call_helper(stack, helper, args, "", "{{", "}}", span));
+            return self.render_filtered(wr, stack, &Data::Str(value), filters);
+        }
+
         match self.find(path, stack) {
-            None => { }
-            Some(value) => {
-                wr.write(self.indent.as_bytes()).unwrap();
+            Some(value) => self.render_filtered(wr, stack, value, filters),
+            None => {
+                if let Some(value) = self.loop_value(path) {
+                    return self.render_filtered(wr, stack, &value, filters);
+                }
 
-                match *value {
-                    Data::Str(ref value) => {
-                        wr.write(value.as_bytes()).unwrap();
+                match try!(self.missing(path, span)) {
+                    Some(value) => {
+                        let mut stack = stack.clone();
+                        self.render_filtered(wr, &mut stack, &value, filters)
                     }
+                    None => Ok(()),
+                }
+            }
+        }
+    }
 
-                    // etags and utags use the default delimiter.
-                    Data::Fun(ref f) => {
-                        let tokens = self.render_fun("", "{{", "}}", &**f.borrow());
-                        self.render(wr, stack, &tokens);
-                    }
+    /// Renders `value` once it has been piped through `filters`. Any
+    /// escaping is done by `wr`, so it happens after the filters.
+    fn render_filtered(
+        &mut self,
+        wr: &mut fmt::Write,
+        stack: &mut Vec<&Data>,
+        value: &Data,
+        filters: &[ast::Filter]
+    ) -> Result<(), Error> {
+        if filters.is_empty() {
+            return self.render_utag_value(wr, stack, value);
+        }
 
-                    ref value => { panic!("unexpected value {:?}", value); }
-                }
+        let mut value = try!(self.apply_filter(value, &filters[0]));
+        for filter in filters[1..].iter() {
+            value = try!(self.apply_filter(&value, filter));
+        }
+
+        self.render_utag_value(wr, stack, &value)
+    }
+
+    fn apply_filter(&self, value: &Data, filter: &ast::Filter) -> Result<Data, Error> {
+        match self.template.ctx.filter(&filter.name) {
+            Some(f) => f.filter(value, &filter.args),
+            None => Err(Error::FilterNotFound(filter.name.clone())),
+        }
+    }
+
+    fn render_utag_value(
+        &mut self,
+        wr: &mut fmt::Write,
+        stack: &mut Vec<&Data>,
+        value: &Data
+    ) -> Result<(), Error> {
+        match *value {
+            Data::Str(ref value) => {
+                try!(wr.write_str(value));
             }
-        };
+            Data::Int(value) => { try!(write!(wr, "{}", value)); }
+            Data::UInt(value) => { try!(write!(wr, "{}", value)); }
+            Data::Float(value) => { try!(write!(wr, "{}", value)); }
+            Data::Bool(value) => { try!(write!(wr, "{}", value)); }
+            Data::Null => { }
+
+            // etags and utags use the default delimiter.
+            Data::Fun(ref f) => {
+                let tokens = try!(self.render_fun("", "{{", "}}", f));
+                try!(self.render(wr, stack, &tokens));
+            }
+            Data::Lambda(ref f) => {
+                let value = try!(self.render_lambda(stack, "", "{{", "}}", f));
+                try!(wr.write_str(&value));
+            }
+
+            // A list or a map has no text of its own to render.
+            Data::Vec(_) | Data::Map(_) => { return Err(Error::UnsupportedType); }
+        }
+
+        Ok(())
     }
 
-    fn render_inverted_section<'b, W: Write>(
+    fn render_inverted_section(
         &mut self,
-        wr: &mut W,
+        wr: &mut fmt::Write,
         stack: &mut Vec<&Data>,
         path: &[String],
-        children: &[Token]
-    ) {
-        match self.find(path, stack) {
+        children: &[Token],
+        span: Span
+    ) -> Result<(), Error> {
+        // `{{^name}}` is how a template checks that a value is missing, so
+        // a missing value is falsey, rather than an error, in strict mode.
+        let substitute;
+        let value = match self.find(path, stack) {
+            Some(value) => Some(value),
+            None => {
+                substitute = match self.loop_value(path) {
+                    Some(value) => Some(value),
+                    None => {
+                        match self.missing(path, span) {
+                            Err(Error::MissingVariable { .. }) => None,
+                            result => try!(result),
+                        }
+                    }
+                };
+                substitute.as_ref()
+            }
+        };
+
+        match value {
             None => { }
-            Some(&Data::Bool(false)) => { }
-            Some(&Data::Vec(ref xs)) if xs.is_empty() => { }
-            Some(_) => { return; }
+            Some(value) if value.is_falsey() => { }
+            Some(_) => { return Ok(()); }
         }
 
-        self.render(wr, stack, children);
+        self.render(wr, stack, children)
     }
 
-    fn render_section<'b, W: Write>(
+    fn render_section(
         &mut self,
-        wr: &mut W,
+        wr: &mut fmt::Write,
         stack: &mut Vec<&Data>,
         path: &[String],
+        args: &[ast::Argument],
         children: &[Token],
         src: &str,
         otag: &str,
-        ctag: &str
-    ) {
+        ctag: &str,
+        span: Span
+    ) -> Result<(), Error> {
+        if let Some(helper) = try!(self.helper(path, args)) {
+            let value = try!(self.call_helper(stack, helper, args, src, otag, ctag, span));
+            try!(wr.write_str(&value));
+            return Ok(());
+        }
+
         match self.find(path, stack) {
-            None => { }
-            Some(value) => {
-                match *value {
-                    Data::Bool(true) => {
-                        self.render(wr, stack, children);
-                    }
-                    Data::Bool(false) => { }
-                    Data::Vec(ref vs) => {
-                        for v in vs.iter() {
-                            stack.push(v);
-                            self.render(wr, stack, children);
-                            stack.pop();
-                        }
-                    }
-                    Data::Map(_) => {
-                        stack.push(value);
-                        self.render(wr, stack, children);
-                        stack.pop();
-                    }
-                    Data::Fun(ref f) => {
-                        let tokens = self.render_fun(src, otag, ctag, &**f.borrow());
-                        self.render(wr, stack, &tokens)
+            Some(value) => self.render_section_value(wr, stack, value, children, src, otag, ctag),
+            None => {
+                let value = match self.loop_value(path) {
+                    Some(value) => Some(value),
+                    None => try!(self.missing(path, span)),
+                };
+
+                match value {
+                    // The value has to outlive the stack it is pushed onto.
+                    Some(value) => {
+                        let mut stack = stack.clone();
+                        self.render_section_value(wr, &mut stack, &value, children, src, otag, ctag)
                     }
-                    _ => { panic!("unexpected value {:?}", value) }
+                    None => Ok(()),
                 }
             }
         }
     }
 
-    fn render_partial<'b, W: Write>(
+    fn render_section_value<'c>(
         &mut self,
-        wr: &mut W,
+        wr: &mut fmt::Write,
+        stack: &mut Vec<&'c Data>,
+        value: &'c Data,
+        children: &[Token],
+        src: &str,
+        otag: &str,
+        ctag: &str
+    ) -> Result<(), Error> {
+        match *value {
+            Data::Bool(true) => {
+                try!(self.render(wr, stack, children));
+            }
+            Data::Bool(false) => { }
+            Data::Str(_) | Data::Int(_) | Data::UInt(_) | Data::Float(_) | Data::Null => {
+                if !value.is_falsey() {
+                    stack.push(value);
+                    try!(self.render(wr, stack, children));
+                    stack.pop();
+                }
+            }
+            Data::Vec(ref vs) => {
+                // The loop metadata, such as `@index`, is worked out from the
+                // position of the item when it is looked up.
+                for (i, v) in vs.iter().enumerate() {
+                    self.loops.push((i, vs.len()));
+                    stack.push(v);
+                    let result = self.render(wr, stack, children);
+                    stack.pop();
+                    self.loops.pop();
+                    try!(result);
+                }
+            }
+            Data::Map(_) => {
+                stack.push(value);
+                try!(self.render(wr, stack, children));
+                stack.pop();
+            }
+            Data::Fun(ref f) => {
+                let tokens = try!(self.render_fun(src, otag, ctag, f));
+                try!(self.render(wr, stack, &tokens));
+            }
+            Data::Lambda(ref f) => {
+                let value = try!(self.render_lambda(stack, src, otag, ctag, f));
+                try!(wr.write_str(&value));
+            }
+        }
+
+        Ok(())
+    }
+
+    fn render_partial(
+        &mut self,
+        wr: &mut fmt::Write,
         stack: &mut Vec<&Data>,
         name: &str,
         indent: &str
-    ) {
-        match self.template.partials.get(name) {
-            None => { }
+    ) -> Result<(), Error> {
+        // A partial with an extension of its own, like `header.html`, may
+        // escape differently from the template that includes it.
+        let template = self.template;
+        let escaper = match template.ctx.extension_escaper(name) {
+            Some(escaper) => mem::replace(&mut self.escaper, escaper),
+            None => self.escaper,
+        };
+
+        let result = match template.partials.get(name) {
             Some(ref tokens) => {
-                let mut indent = format!("{}{}", self.indent, indent);
+                self.render_indented(wr, stack, indent, &tokens)
+            }
+            None => {
+                match self.template.load_partial(name) {
+                    Ok(Some(tokens)) => self.render_indented(wr, stack, indent, &tokens),
+                    Ok(None) => Ok(()),
+                    Err(err) => Err(err),
+                }
+            }
+        };
+
+        self.escaper = escaper;
+        result
+    }
+
+    fn render_dynamic_partial(
+        &mut self,
+        wr: &mut fmt::Write,
+        stack: &mut Vec<&Data>,
+        path: &[String],
+        indent: &str
+    ) -> Result<(), Error> {
+        match try!(self.dynamic_name(path, stack)) {
+            Some(name) => self.render_partial(wr, stack, &name, indent),
+            None => Ok(()),
+        }
+    }
+
+    /// Looks up the name of a dynamic partial or parent in the data. Since
+    /// the name comes from the data, it can't climb out of the template path,
+    /// so a name with a `..` component is an error. Names in subdirectories,
+    /// like `widgets/button`, are fine. A leading `/` is left to the loader,
+    /// which treats it as not found.
+    fn dynamic_name(
+        &self,
+        path: &[String],
+        stack: &mut Vec<&Data>
+    ) -> Result<Option<String>, Error> {
+        match self.find(path, stack) {
+            Some(&Data::Str(ref name)) => {
+                let climbs = name.split(|c: char| c == '/' || c == '\\')
+                    .any(|part| part == "..");
+
+                if climbs {
+                    Err(Error::InvalidPartialName(name.clone()))
+                } else {
+                    Ok(Some(name.clone()))
+                }
+            }
+            _ => Ok(None),
+        }
+    }
+
+    fn render_indented(
+        &mut self,
+        wr: &mut fmt::Write,
+        stack: &mut Vec<&Data>,
+        indent: &str,
+        tokens: &[Token]
+    ) -> Result<(), Error> {
+        let mut indent = format!("{}{}", self.indent, indent);
+
+        mem::swap(&mut self.indent, &mut indent);
+        let result = self.render(wr, stack, tokens);
+        mem::swap(&mut self.indent, &mut indent);
+
+        result
+    }
 
-                mem::swap(&mut self.indent, &mut indent);
-                self.render(wr, stack, &tokens);
-                mem::swap(&mut self.indent, &mut indent);
+    fn render_parent(
+        &mut self,
+        wr: &mut fmt::Write,
+        stack: &mut Vec<&Data>,
+        name: &str,
+        indent: &str,
+        children: &[Token]
+    ) -> Result<(), Error> {
+        // Only the blocks inside a parent matter, the rest is ignored.
+        let mut blocks = HashMap::new();
+        for child in children.iter() {
+            if let Token::Block(ref name, _, ref children, _, _, _, _) = *child {
+                blocks.insert(name.clone(), children.clone());
+            }
+        }
+
+        // The blocks we are already overriding come from further out, so
+        // they take precedence over the ones in this parent.
+        for (name, tokens) in self.blocks.iter() {
+            blocks.insert(name.clone(), tokens.clone());
+        }
+
+        // Like partials, a `{{<*name}}` parent is named by the data.
+        let name = if name.starts_with("*") {
+            let path: Vec<String> = name.slice_from(1).trim().split_terminator('.')
+                .map(|x| x.to_string())
+                .collect();
+
+            match try!(self.dynamic_name(&path, stack)) {
+                Some(name) => name,
+                None => { return Ok(()); }
+            }
+        } else {
+            name.to_string()
+        };
+
+        mem::swap(&mut self.blocks, &mut blocks);
+        let result = self.render_partial(wr, stack, &name, indent);
+        mem::swap(&mut self.blocks, &mut blocks);
+
+        result
+    }
+
+    fn render_block(
+        &mut self,
+        wr: &mut fmt::Write,
+        stack: &mut Vec<&Data>,
+        name: &str,
+        indent: &str,
+        children: &[Token]
+    ) -> Result<(), Error> {
+        match self.blocks.get(name).cloned() {
+            Some(tokens) => {
+                self.render_indented(wr, stack, indent, &tokens)
+            }
+            None => {
+                // The default content is indented already.
+                self.render(wr, stack, children)
             }
         }
     }
@@ -290,10 +867,103 @@ impl<'a> RenderContext<'a> {
         src: &str,
         otag: &str,
         ctag: &str,
-        f: &Fn(String) -> String
-    ) -> Vec<parser::Token> {
-        let src = (*f)(src.to_string());
+        f: &RefCell<Box<FnMut(String) -> String>>
+    ) -> Result<Vec<parser::Token>, Error> {
+        let src = {
+            let mut f = try!(borrow_lambda(f));
+            (*f)(src.to_string())
+        };
+        self.compile(&src, otag, ctag)
+    }
 
+    /// Calls a lambda, whose output is used as it is rather than compiled.
+    fn render_lambda(
+        &mut self,
+        stack: &mut Vec<&Data>,
+        src: &str,
+        otag: &str,
+        ctag: &str,
+        f: &RefCell<Box<FnMut(&mut LambdaContext) -> Result<String, Error>>>
+    ) -> Result<String, Error> {
+        let mut f = try!(borrow_lambda(f));
+        let mut ctx = LambdaContext {
+            render_ctx: self,
+            stack: stack,
+            src: src,
+            otag: otag,
+            ctag: ctag,
+        };
+
+        (*f)(&mut ctx)
+    }
+
+    /// Returns the helper that a tag or section named `path` calls, if it
+    /// calls one. Anything with arguments has to be a helper, but otherwise
+    /// a name that isn't a helper is looked up in the data.
+    fn helper(&self, path: &[String], args: &[ast::Argument]) -> Result<Option<&'a Helper>, Error> {
+        let template = self.template;
+        let helper = if path.len() == 1 { template.ctx.helper(&path[0]) } else { None };
+
+        match helper {
+            Some(helper) => Ok(Some(helper)),
+            None if args.is_empty() => Ok(None),
+            None => Err(Error::HelperNotFound(path.connect("."))),
+        }
+    }
+
+    fn call_helper(
+        &mut self,
+        stack: &mut Vec<&Data>,
+        helper: &Helper,
+        args: &[ast::Argument],
+        src: &str,
+        otag: &str,
+        ctag: &str,
+        span: Span
+    ) -> Result<String, Error> {
+        // Literals, and the values used for missing ones, aren't in the data,
+        // so they are kept here while the helper borrows them.
+        let mut owned = Vec::with_capacity(args.len());
+        for arg in args.iter() {
+            owned.push(match *arg {
+                ast::Argument::Path(ref path) => {
+                    match self.find(path, stack) {
+                        Some(_) => None,
+                        None => {
+                            match self.loop_value(path) {
+                                Some(value) => Some(value),
+                                None => Some(try!(self.missing(path, span)).unwrap_or(Data::Null)),
+                            }
+                        }
+                    }
+                }
+                ast::Argument::Str(ref s) => Some(Data::Str(s.clone())),
+                ast::Argument::Int(n) => Some(Data::Int(n)),
+            });
+        }
+
+        let mut values = Vec::with_capacity(args.len());
+        for (arg, value) in args.iter().zip(owned.iter()) {
+            values.push(match (arg, value) {
+                (_, &Some(ref value)) => value,
+                (&ast::Argument::Path(ref path), &None) => self.find(path, stack).unwrap(),
+                _ => unreachable!(),
+            });
+        }
+
+        let mut ctx = LambdaContext {
+            render_ctx: self,
+            stack: stack,
+            src: src,
+            otag: otag,
+            ctag: ctag,
+        };
+
+        helper.call(&values, &mut ctx)
+    }
+
+    /// Compiles the template fragment `src`, such as the output of a lambda.
+    fn compile(&self, src: &str, otag: &str, ctag: &str) -> Result<Vec<Token>, Error> {
         let compiler = Compiler::new_with(
             self.template.ctx.clone(),
             src.chars(),
@@ -301,11 +971,47 @@ impl<'a> RenderContext<'a> {
             otag.to_string(),
             ctag.to_string());
 
-        let (tokens, _) = compiler.compile();
-        tokens
+        let (tokens, _) = try!(compiler.compile());
+        Ok(tokens)
     }
 
-    fn find<'b, 'c>(&self, path: &[String], stack: &mut Vec<&'c Data>) -> Option<&'c Data> {
+    /// Called when `path` isn't found in the data, to ask the `on_missing`
+    /// callback for a value to use instead. Without one, nothing is
+    /// rendered, unless in strict mode, where that is an error.
+    fn missing(&mut self, path: &[String], span: Span) -> Result<Option<Data>, Error> {
+        if let Some(ref mut on_missing) = self.on_missing {
+            if let Some(value) = (*on_missing)(path) {
+                return Ok(Some(value));
+            }
+        }
+
+        if self.template.ctx.is_strict() {
+            Err(Error::MissingVariable { path: path.connect("."), span: span })
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Returns the metadata of the item being rendered in the innermost
+    /// list, if `path` is `@index`, `@index1`, `@first`, `@last` or
+    /// `@length`.
+    fn loop_value(&self, path: &[String]) -> Option<Data> {
+        let (index, len) = match self.loops.last() {
+            Some(&(index, len)) if path.len() == 1 => (index, len),
+            _ => { return None; }
+        };
+
+        match path[0].as_slice() {
+            "@index" => Some(Data::UInt(index as u64)),
+            "@index1" => Some(Data::UInt(index as u64 + 1)),
+            "@first" => Some(Data::Bool(index == 0)),
+            "@last" => Some(Data::Bool(index + 1 == len)),
+            "@length" => Some(Data::UInt(len as u64)),
+            _ => None,
+        }
+    }
+
+    fn find<'c>(&self, path: &[String], stack: &[&'c Data]) -> Option<&'c Data> {
         // If we have an empty path, we just want the top value in our stack.
         if path.is_empty() {
             match stack.last() {
@@ -328,7 +1034,9 @@ impl<'a> RenderContext<'a> {
                         None => { }
                     }
                 }
-                _ => { panic!("expect map: {:?}", path) }
+                // Only maps have names to look up, so skip over the other
+                // values, like the strings of a list being iterated over.
+                _ => { }
             }
         }
 
@@ -346,6 +1054,11 @@ impl<'a> RenderContext<'a> {
                         None => { return None; }
                     }
                 }
+                // Enum variants without arguments are encoded as their name,
+                // so a string answers to its own value. That way
+                // `{{#status.Pending}}` works for them just as it does for
+                // the variants encoded as maps.
+                Data::Str(ref s) if s == part => { }
                 _ => { return None; }
             }
         }
@@ -354,21 +1067,83 @@ impl<'a> RenderContext<'a> {
     }
 }
 
+/// Borrows a lambda to call it. It is already borrowed if it is being called
+/// further up, such as by a lambda rendering itself, which is an error.
+fn borrow_lambda<T: ?Sized>(f: &RefCell<Box<T>>) -> Result<RefMut<Box<T>>, Error> {
+    match f.try_borrow_mut() {
+        Some(f) => Ok(f),
+        None => Err(Error::RecursiveLambda),
+    }
+}
+
+/// `LambdaContext` is given to the lambdas of `Data::Lambda`, and to
+/// helpers. Through it, a lambda can see the source of its section, and
+/// render templates and look up values with the data it was called with.
+pub struct LambdaContext<'r, 'a: 'r, 'c: 'r> {
+    render_ctx: &'r mut RenderContext<'a>,
+    stack: &'r mut Vec<&'c Data>,
+    src: &'r str,
+    otag: &'r str,
+    ctag: &'r str,
+}
+
+impl<'r, 'a, 'c> LambdaContext<'r, 'a, 'c> {
+    /// Returns the unrendered source of the section the lambda was called
+    /// for, or an empty string for a `{{name}}` tag.
+    pub fn source(&self) -> &str {
+        self.src
+    }
+
+    /// Returns the opening and closing delimiters in use where the lambda was
+    /// called.
+    pub fn delimiters(&self) -> (&str, &str) {
+        (self.otag, self.ctag)
+    }
+
+    /// Renders the template `src` with the current data and delimiters.
+    pub fn render(&mut self, src: &str) -> Result<String, Error> {
+        let tokens = try!(self.render_ctx.compile(src, self.otag, self.ctag));
+
+        let mut s = String::new();
+        try!(self.render_ctx.render(&mut s, self.stack, &tokens));
+        Ok(s)
+    }
+
+    /// Looks up the value of a dotted `path`, like `user.name`, in the
+    /// current data. Loop metadata, like `@index`, isn't part of the data,
+    /// so it isn't found here.
+    pub fn lookup(&self, path: &str) -> Option<&Data> {
+        let path: Vec<String> = if path == "." {
+            Vec::new()
+        } else {
+            path.split_terminator('.').map(|x| x.to_string()).collect()
+        };
+
+        self.render_ctx.find(&path, &self.stack)
+    }
+}
+
 #[cfg(test)]
 mod tests {
+    use std::ascii::AsciiExt;
     use std::cell::RefCell;
-    use std::str;
-    use std::io::{File, TempDir};
     use std::collections::HashMap;
+    use std::fmt;
+    use std::str;
+    use std::io::{self, File, TempDir, Write};
     use serialize::json;
     use serialize::Encodable;
 
     use context::Context;
     use data::Data;
     use encoder::Encoder;
+    use escape::Escaper;
     use error::Error;
+    use map::Map;
+    use parser::Span;
     use template::Template;
 
+    use builder::MapBuilder;
     use super::super::compile_str;
 
     #[derive(Encodable)]
@@ -378,12 +1153,8 @@ mod tests {
         template: &str,
         data: &T,
     ) -> Result<String, Error> {
-        let template = compile_str(template);
-
-        let mut wr = Vec::new();
-        try!(template.render(&mut wr, data));
-
-        Ok(String::from_utf8(wr).unwrap().to_string())
+        let template = try!(compile_str(template));
+        template.render_to_string(data)
     }
 
     #[test]
@@ -412,62 +1183,465 @@ mod tests {
     }
 
     fn render_data<'a>(template: &Template, data: &Data) -> String {
+        template.render_data_to_string(data).unwrap()
+    }
+
+    struct BrokenPipe;
+
+    impl Write for BrokenPipe {
+        fn write(&mut self, _buf: &[u8]) -> io::Result<usize> {
+            Err(io::Error::new(io::ErrorKind::BrokenPipe, "broken pipe", None))
+        }
+
+        fn flush(&mut self) -> io::Result<()> { Ok(()) }
+    }
+
+    #[test]
+    fn test_render_extension_escapers() {
+        let mut data = Map::new();
+        data.insert("name".to_string(), Data::Str("<\"a\">".to_string()));
+        let data = Data::Map(data);
+
+        // Without the default extension escapers, the extension changes
+        // nothing.
+        let ctx = Context::new(Path::new("src/test-data"));
+        let template = ctx.compile_path(Path::new("page.json")).unwrap();
+        assert_eq!(
+            render_data(&template, &data),
+            "{\"name\": \"&lt;&quot;a&quot;&gt;\", \"html\": \"<b>&lt;&quot;a&quot;&gt;</b>\"}\n"
+                .to_string());
+
+        let ctx = Context::new(Path::new("src/test-data")).with_default_extension_escapers();
+
+        let template = ctx.compile_path(Path::new("page.json")).unwrap();
+        assert_eq!(
+            render_data(&template, &data),
+            "{\"name\": \"<\\\"a\\\">\", \"html\": \"<b>&lt;&quot;a&quot;&gt;</b>\"}\n".to_string());
+
+        // Partials without an extension use the escaper of their template.
+        let template = ctx.compile("{{name}} {{>user}}".chars()).unwrap();
+        assert_eq!(
+            render_data(&template, &data),
+            "&lt;&quot;a&quot;&gt; <strong>&lt;&quot;a&quot;&gt;</strong>\n".to_string());
+
+        let template = ctx.compile_path(Path::new("page.json.mustache")).unwrap();
+        assert_eq!(
+            render_data(&template, &data),
+            "{\"name\": \"<\\\"a\\\">\", \"html\": \"<b>&lt;&quot;a&quot;&gt;</b>\"}\n".to_string());
+    }
+
+    #[test]
+    fn test_render_bytes() {
+        let template = compile_str("hello {{name}}").unwrap();
+
+        let mut ctx = Map::new();
+        ctx.insert("name".to_string(), Data::Str("<world>".to_string()));
+
         let mut wr = Vec::new();
-        template.render_data(&mut wr, data);
-        String::from_utf8(wr).unwrap().to_string()
+        template.render_data(&mut wr, &Data::Map(ctx)).unwrap();
+        assert_eq!(wr, b"hello &lt;world&gt;".to_vec());
+    }
+
+    #[test]
+    fn test_render_io_error() {
+        let template = compile_str("hello {{name}}").unwrap();
+
+        match template.render_data(&mut BrokenPipe, &Data::Map(Map::new())) {
+            Err(Error::IoError(ref err)) => assert_eq!(err.kind(), io::ErrorKind::BrokenPipe),
+            result => panic!("unexpected result {:?}", result),
+        }
+    }
+
+    #[test]
+    fn test_render_strict() {
+        let ctx = Context::new(Path::new(".")).with_strict();
+
+        let mut user = Map::new();
+        user.insert("name".to_string(), Data::Str("a".to_string()));
+        let mut data = Map::new();
+        data.insert("user".to_string(), Data::Map(user));
+        let data = Data::Map(data);
+
+        let template = ctx.compile("hi {{user.name}}".chars()).unwrap();
+        assert_eq!(template.render_data_to_string(&data), Ok("hi a".to_string()));
+
+        let template = ctx.compile("hi\n {{user.nmae}}".chars()).unwrap();
+        assert_eq!(
+            template.render_data_to_string(&data),
+            Err(Error::MissingVariable {
+                path: "user.nmae".to_string(),
+                span: Span { start: 4, end: 17, line: 2, col: 2 },
+            }));
+
+        let template = ctx.compile("{{#users}}{{name}}{{/users}}".chars()).unwrap();
+        assert_eq!(
+            template.render_data_to_string(&data),
+            Err(Error::MissingVariable {
+                path: "users".to_string(),
+                span: Span { start: 0, end: 28, line: 1, col: 1 },
+            }));
+
+        // Inverted sections render for missing values, even in strict mode.
+        let template = ctx.compile("{{^users}}none{{/users}}".chars()).unwrap();
+        assert_eq!(template.render_data_to_string(&data), Ok("none".to_string()));
+
+        // Without strict mode, missing values render as nothing.
+        let template = compile_str("hi {{user.nmae}}{{#users}}x{{/users}}").unwrap();
+        assert!(!template.is_strict());
+        assert_eq!(template.render_data_to_string(&data), Ok("hi ".to_string()));
+
+        // Strict mode can be turned on for a template on its own too.
+        let template = template.with_strict();
+        assert!(template.is_strict());
+        match template.render_data_to_string(&data) {
+            Err(Error::MissingVariable { ref path, .. }) => {
+                assert_eq!(*path, "user.nmae".to_string());
+            }
+            result => panic!("unexpected result {:?}", result),
+        }
+    }
+
+    #[test]
+    fn test_render_missing() {
+        let template = compile_str("{{#names}}{{.}} {{user.name}}, {{/names}}{{^ok}}!{{/ok}}")
+            .unwrap();
+
+        let mut data = Map::new();
+        data.insert("names".to_string(), Data::Vec(vec!(Data::Str("a".to_string()))));
+        let data = Data::Map(data);
+
+        let mut missing = Vec::new();
+        let mut bytes = Vec::new();
+        template.renderer().on_missing(|path: &[String]| {
+            let path = path.connect(".");
+            missing.push(path.clone());
+
+            if path == "ok" {
+                Some(Data::Bool(true))
+            } else {
+                Some(Data::Str(format!("[missing: {}]", path)))
+            }
+        }).render_data(&mut bytes, &data).unwrap();
+
+        assert_eq!(str::from_utf8(&bytes).unwrap(), "a [missing: user.name], ");
+        assert_eq!(missing, vec!("user.name".to_string(), "ok".to_string()));
+
+        // A value from the callback is used even in strict mode.
+        let template = Context::new(Path::new(".")).with_strict()
+            .compile("{{#user}}{{name}}{{/user}}".chars())
+            .unwrap();
+
+        let mut bytes = Vec::new();
+        template.renderer().on_missing(|path: &[String]| {
+            if path[0] == "user" {
+                let mut user = Map::new();
+                user.insert("name".to_string(), Data::Str("b".to_string()));
+                Some(Data::Map(user))
+            } else {
+                None
+            }
+        }).render_data(&mut bytes, &data).unwrap();
+
+        assert_eq!(str::from_utf8(&bytes).unwrap(), "b");
+
+        // The same renderer can be used more than once, and to render to a
+        // string.
+        let mut renderer = template.renderer().on_missing(|_: &[String]| {
+            Some(Data::Str("c".to_string()))
+        });
+        assert_eq!(renderer.render_data_to_string(&data), Ok("c".to_string()));
+        assert_eq!(renderer.render_data_to_string(&data), Ok("c".to_string()));
+
+        let template = compile_str("hello {{name}} {{missing}}").unwrap();
+        let ctx = Name { name: "world".to_string() };
+        let result = template.renderer().on_missing(|path: &[String]| {
+            Some(Data::Str(format!("[missing: {}]", path.connect("."))))
+        }).render_to_string(&ctx);
+        assert_eq!(result, Ok("hello world [missing: missing]".to_string()));
+    }
+
+    #[test]
+    fn test_render_find_skips_non_maps() {
+        // Names are looked up in the maps on the stack, skipping the values,
+        // like these strings, that don't have names.
+        let template = compile_str("{{#names}}{{.}} {{greeting}}, {{/names}}").unwrap();
+
+        let mut data = Map::new();
+        data.insert("greeting".to_string(), Data::Str("hi".to_string()));
+        data.insert("names".to_string(), Data::Vec(vec!(
+            Data::Str("a".to_string()),
+            Data::Str("b".to_string()))));
+
+        assert_eq!(render_data(&template, &Data::Map(data)), "a hi, b hi, ".to_string());
+    }
+
+    #[test]
+    fn test_render_numbers() {
+        let template = compile_str("{{a}} {{b}} {{c}} [{{d}}] {{#a}}{{.}}{{/a}}{{#zero}}0{{/zero}}\
+                                    {{^zero}}no zero{{/zero}}{{^d}}, no d{{/d}}").unwrap();
+
+        let mut ctx = Map::new();
+        ctx.insert("a".to_string(), Data::Int(-1));
+        ctx.insert("b".to_string(), Data::UInt(2));
+        ctx.insert("c".to_string(), Data::Float(0.5));
+        ctx.insert("d".to_string(), Data::Null);
+        ctx.insert("zero".to_string(), Data::UInt(0));
+
+        assert_eq!(render_data(&template, &Data::Map(ctx)),
+                   "-1 2 0.5 [] -1no zero, no d".to_string());
+
+        #[derive(Encodable)]
+        struct Count { count: u32 }
+
+        let template = compile_str("{{count}} {{#count}}items{{/count}}").unwrap();
+        assert_eq!(template.render_to_string(&Count { count: 3 }),
+                   Ok("3 items".to_string()));
+    }
+
+    #[test]
+    fn test_render_other_values() {
+        let mut ctx = Map::new();
+        ctx.insert("yes".to_string(), Data::Bool(true));
+        ctx.insert("no".to_string(), Data::Bool(false));
+        ctx.insert("list".to_string(), Data::Vec(vec!(Data::UInt(1))));
+        ctx.insert("map".to_string(), Data::Map(Map::new()));
+        let data = Data::Map(ctx);
+
+        // Booleans render the way filters see them.
+        let template = compile_str("{{yes}} {{{no}}}").unwrap();
+        assert_eq!(template.render_data_to_string(&data), Ok("true false".to_string()));
+
+        // Lists and maps have no text, so rendering them is an error.
+        for src in ["{{list}}", "{{{map}}}"].iter() {
+            let template = compile_str(src).unwrap();
+            assert_eq!(template.render_data_to_string(&data), Err(Error::UnsupportedType));
+        }
+    }
+
+    #[test]
+    fn test_render_lambda() {
+        let template = compile_str("{{#bold}}Hi {{name}}.{{/bold}} {{user}} {{{raw}}}").unwrap();
+
+        let data = MapBuilder::new()
+            .insert_str("name".to_string(), "<a>".to_string())
+            .insert_lambda("bold".to_string(), |lambda| {
+                let src = lambda.source().to_string();
+                let text = try!(lambda.render(&src));
+                Ok(format!("<b>{}</b>", text))
+            })
+            .insert_lambda("user".to_string(), |lambda| {
+                match lambda.lookup("name") {
+                    Some(&Data::Str(ref name)) => Ok(format!("user {}", name)),
+                    _ => Ok("nobody".to_string()),
+                }
+            })
+            .insert_lambda("raw".to_string(), |lambda| {
+                // The output isn't compiled again.
+                let (otag, ctag) = lambda.delimiters();
+                Ok(format!("{}name{}", otag, ctag))
+            })
+            .build();
+
+        assert_eq!(render_data(&template, &data),
+                   "<b>Hi &lt;a&gt;.</b> user &lt;a&gt; {{name}}".to_string());
+    }
+
+    #[test]
+    fn test_render_stateful_lambdas() {
+        let template = compile_str("{{count}}, {{count}}, {{#upper}}{{name}}{{/upper}}").unwrap();
+
+        let mut count = 0u32;
+        let mut names = vec!("b".to_string(), "a".to_string());
+        let data = MapBuilder::new()
+            .insert_fn("count".to_string(), move |_| {
+                count += 1;
+                count.to_string()
+            })
+            .insert_lambda("name".to_string(), move |_| Ok(names.pop().unwrap()))
+            .insert_lambda("upper".to_string(), |lambda| {
+                let src = lambda.source().to_string();
+                let text = try!(lambda.render(&src));
+                Ok(text.to_ascii_uppercase())
+            })
+            .build();
+
+        assert_eq!(render_data(&template, &data), "1, 2, A".to_string());
+        assert_eq!(render_data(&template, &data), "3, 4, B".to_string());
+
+        // A lambda can't be called again while it is running.
+        let template = compile_str("{{#again}}x{{/again}}").unwrap();
+        let data = MapBuilder::new()
+            .insert_lambda("again".to_string(), |lambda| lambda.render("{{#again}}y{{/again}}"))
+            .build();
+
+        assert_eq!(template.render_data_to_string(&data), Err(Error::RecursiveLambda));
+
+        // That includes a section of the lambda nested inside its own.
+        let template = compile_str("{{#bold}}a {{#bold}}b{{/bold}}{{/bold}}").unwrap();
+        let data = MapBuilder::new()
+            .insert_lambda("bold".to_string(), |lambda| {
+                let src = lambda.source().to_string();
+                let text = try!(lambda.render(&src));
+                Ok(format!("<b>{}</b>", text))
+            })
+            .build();
+
+        assert_eq!(template.render_data_to_string(&data), Err(Error::RecursiveLambda));
+    }
+
+    #[test]
+    fn test_render_filters() {
+        let repeat = |value: &Data, args: &[String]| {
+            match (value, args.first()) {
+                (&Data::Str(ref s), Some(count)) => {
+                    let mut repeated = String::new();
+                    for _ in 0..count.parse::<usize>().unwrap_or(1) {
+                        repeated.push_str(s);
+                    }
+                    Ok(Data::Str(repeated))
+                }
+                _ => Err(Error::InvalidFilter("repeat expects a string".to_string())),
+            }
+        };
+        let ctx = Context::new(Path::new(".")).with_filter("repeat", repeat);
+
+        let data = MapBuilder::new()
+            .insert_str("name".to_string(), " <tom bebbington> ".to_string())
+            .insert_uint("count".to_string(), 5)
+            .build();
+
+        // Values are escaped after they are filtered.
+        let template = ctx.compile("{{ name | trim | upper | truncate:4:... }}".chars()).unwrap();
+        assert_eq!(template.render_data_to_string(&data), Ok("&lt;TOM...".to_string()));
+
+        let template = ctx.compile("{{{name | trim | truncate:3:\"|:\"}}}".chars()).unwrap();
+        assert_eq!(template.render_data_to_string(&data), Ok("<to|:".to_string()));
+
+        let template = ctx.compile("{{{name|trim|repeat:2}}}".chars()).unwrap();
+        assert_eq!(
+            template.render_data_to_string(&data),
+            Ok("<tom bebbington><tom bebbington>".to_string()));
+
+        let template = ctx.compile("{{count | repeat:2}}".chars()).unwrap();
+        assert_eq!(
+            template.render_data_to_string(&data),
+            Err(Error::InvalidFilter("repeat expects a string".to_string())));
+
+        let template = ctx.compile("{{name | shout}}".chars()).unwrap();
+        assert_eq!(
+            template.render_data_to_string(&data),
+            Err(Error::FilterNotFound("shout".to_string())));
     }
 
     #[test]
     fn test_render_sections() {
-        let ctx = HashMap::new();
-        let template = compile_str("0{{#a}}1 {{n}} 3{{/a}}5");
+        let ctx = Map::new();
+        let template = compile_str("0{{#a}}1 {{n}} 3{{/a}}5").unwrap();
 
         assert_eq!(render_data(&template, &Data::Map(ctx)), "05".to_string());
 
-        let mut ctx = HashMap::new();
+        let mut ctx = Map::new();
         ctx.insert("a".to_string(), Data::Vec(Vec::new()));
 
         assert_eq!(render_data(&template, &Data::Map(ctx)), "05".to_string());
 
-        let mut ctx = HashMap::new();
+        let mut ctx = Map::new();
         ctx.insert("a".to_string(), Data::Vec(Vec::new()));
         assert_eq!(render_data(&template, &Data::Map(ctx)), "05".to_string());
 
-        let mut ctx0 = HashMap::new();
-        let ctx1 = HashMap::new();
+        let mut ctx0 = Map::new();
+        let ctx1 = Map::new();
         ctx0.insert("a".to_string(), Data::Vec(vec!(Data::Map(ctx1))));
 
         assert_eq!(render_data(&template, &Data::Map(ctx0)), "01  35".to_string());
 
-        let mut ctx0 = HashMap::new();
-        let mut ctx1 = HashMap::new();
+        let mut ctx0 = Map::new();
+        let mut ctx1 = Map::new();
         ctx1.insert("n".to_string(), Data::Str("a".to_string()));
         ctx0.insert("a".to_string(), Data::Vec(vec!(Data::Map(ctx1))));
         assert_eq!(render_data(&template, &Data::Map(ctx0)), "01 a 35".to_string());
 
-        let mut ctx = HashMap::new();
+        let mut ctx = Map::new();
         ctx.insert("a".to_string(), Data::Fun(RefCell::new(|_text| "foo".to_string())));
         assert_eq!(render_data(&template, &Data::Map(ctx)), "0foo5".to_string());
+
+        // A string is pushed onto the stack, unless it's empty, which is falsey.
+        let template = compile_str("{{#a}}[{{.}}]{{/a}}{{^a}}none{{/a}}").unwrap();
+
+        let mut ctx = Map::new();
+        ctx.insert("a".to_string(), Data::Str("b".to_string()));
+        assert_eq!(render_data(&template, &Data::Map(ctx)), "[b]".to_string());
+
+        let mut ctx = Map::new();
+        ctx.insert("a".to_string(), Data::Str("".to_string()));
+        assert_eq!(render_data(&template, &Data::Map(ctx)), "none".to_string());
+    }
+
+    #[test]
+    fn test_render_loop_metadata() {
+        let data = MapBuilder::new()
+            .insert_vec("items".to_string(), |builder| {
+                builder
+                    .push_str("a".to_string())
+                    .push_str("b".to_string())
+                    .push_str("c".to_string())
+            })
+            .insert_vec("rows".to_string(), |builder| {
+                builder
+                    .push_map(|builder| {
+                        builder.insert_vec("cols".to_string(), |builder| {
+                            builder.push_null().push_null()
+                        })
+                    })
+                    .push_map(|builder| {
+                        builder.insert_vec("cols".to_string(), |builder| builder.push_null())
+                    })
+            })
+            .build();
+
+        let template = compile_str(
+            "{{#items}}{{@index1}} of {{@length}}: {{.}}{{^@last}}, {{/@last}}{{/items}}").unwrap();
+        assert_eq!(render_data(&template, &data), "1 of 3: a, 2 of 3: b, 3 of 3: c".to_string());
+
+        let template = compile_str("{{#items}}{{#@first}}[{{/@first}}{{@index}}{{/items}}")
+            .unwrap();
+        assert_eq!(render_data(&template, &data), "[012".to_string());
+
+        // Nested loops see the metadata of the innermost one.
+        let template = compile_str("{{#rows}}{{@index}}:{{#cols}}{{@index}}{{/cols}};{{/rows}}")
+            .unwrap();
+        assert_eq!(render_data(&template, &data), "0:01;1:0;".to_string());
+
+        // Outside of a loop there is no metadata.
+        let template = compile_str("{{@index}}").unwrap();
+        assert_eq!(render_data(&template, &data), "".to_string());
+
+        // Helpers can be given the metadata as arguments.
+        let template = Context::new(Path::new(".")).with_helpers()
+            .compile("{{#items}}{{#if_eq @index 1}}{{.}}{{/if_eq}}{{/items}}".chars())
+            .unwrap();
+        assert_eq!(render_data(&template, &data), "b".to_string());
+
     }
 
     #[test]
     fn test_render_inverted_sections() {
-        let template = compile_str("0{{^a}}1 3{{/a}}5");
+        let template = compile_str("0{{^a}}1 3{{/a}}5").unwrap();
 
-        let ctx = HashMap::new();
+        let ctx = Map::new();
         assert_eq!(render_data(&template, &Data::Map(ctx)), "01 35".to_string());
 
-        let mut ctx = HashMap::new();
+        let mut ctx = Map::new();
         ctx.insert("a".to_string(), Data::Vec(vec!()));
         assert_eq!(render_data(&template, &Data::Map(ctx)), "01 35".to_string());
 
-        let mut ctx0 = HashMap::new();
-        let ctx1 = HashMap::new();
+        let mut ctx0 = Map::new();
+        let ctx1 = Map::new();
         ctx0.insert("a".to_string(), Data::Vec(vec!(Data::Map(ctx1))));
         assert_eq!(render_data(&template, &Data::Map(ctx0)), "05".to_string());
 
-        let mut ctx0 = HashMap::new();
-        let mut ctx1 = HashMap::new();
+        let mut ctx0 = Map::new();
+        let mut ctx1 = Map::new();
         ctx1.insert("n".to_string(), Data::Str("a".to_string()));
         ctx0.insert("a".to_string(), Data::Vec(vec!(Data::Map(ctx1))));
         assert_eq!(render_data(&template, &Data::Map(ctx0)), "05".to_string());
@@ -479,32 +1653,32 @@ mod tests {
             .compile_path(Path::new("base"))
             .unwrap();
 
-        let ctx = HashMap::new();
+        let ctx = Map::new();
         assert_eq!(render_data(&template, &Data::Map(ctx)), "<h2>Names</h2>\n".to_string());
 
-        let mut ctx = HashMap::new();
+        let mut ctx = Map::new();
         ctx.insert("names".to_string(), Data::Vec(vec!()));
         assert_eq!(render_data(&template, &Data::Map(ctx)), "<h2>Names</h2>\n".to_string());
 
-        let mut ctx0 = HashMap::new();
-        let ctx1 = HashMap::new();
+        let mut ctx0 = Map::new();
+        let ctx1 = Map::new();
         ctx0.insert("names".to_string(), Data::Vec(vec!(Data::Map(ctx1))));
         assert_eq!(
             render_data(&template, &Data::Map(ctx0)),
             "<h2>Names</h2>\n  <strong></strong>\n\n".to_string());
 
-        let mut ctx0 = HashMap::new();
-        let mut ctx1 = HashMap::new();
+        let mut ctx0 = Map::new();
+        let mut ctx1 = Map::new();
         ctx1.insert("name".to_string(), Data::Str("a".to_string()));
         ctx0.insert("names".to_string(), Data::Vec(vec!(Data::Map(ctx1))));
         assert_eq!(
             render_data(&template, &Data::Map(ctx0)),
             "<h2>Names</h2>\n  <strong>a</strong>\n\n".to_string());
 
-        let mut ctx0 = HashMap::new();
-        let mut ctx1 = HashMap::new();
+        let mut ctx0 = Map::new();
+        let mut ctx1 = Map::new();
         ctx1.insert("name".to_string(), Data::Str("a".to_string()));
-        let mut ctx2 = HashMap::new();
+        let mut ctx2 = Map::new();
         ctx2.insert("name".to_string(), Data::Str("<b>".to_string()));
         ctx0.insert("names".to_string(), Data::Vec(vec!(Data::Map(ctx1), Data::Map(ctx2))));
         assert_eq!(
@@ -512,6 +1686,106 @@ mod tests {
             "<h2>Names</h2>\n  <strong>a</strong>\n\n  <strong>&lt;b&gt;</strong>\n\n".to_string());
     }
 
+    /// Escapes spaces, so tests can tell whether whitespace was escaped.
+    struct Spaces;
+
+    impl Escaper for Spaces {
+        fn escape(&self, s: &str, wr: &mut fmt::Write) -> fmt::Result {
+            wr.write_str(&s.replace(" ", "_"))
+        }
+    }
+
+    #[test]
+    fn test_render_partial_indent() {
+        let mut partials = HashMap::new();
+        partials.insert("name".to_string(), "{{name}}\n{{{name}}}\n".to_string());
+
+        let template = Context::new(Path::new("."))
+            .with_loader(partials)
+            .with_escaper(Spaces)
+            .compile("  {{>name}}".chars())
+            .unwrap();
+
+        let mut ctx = Map::new();
+        ctx.insert("name".to_string(), Data::Str("a b".to_string()));
+
+        // The indent is written as is, even for tags that escape their value.
+        assert_eq!(render_data(&template, &Data::Map(ctx)), "  a_b\n  a b\n".to_string());
+    }
+
+    #[test]
+    fn test_render_inheritance() {
+        let ctx = Context::new(Path::new("src/test-data"));
+
+        let template = ctx.compile("{{<layout}}{{/layout}}".chars()).unwrap();
+        assert_eq!(
+            render_data(&template, &Data::Map(Map::new())),
+            "<title>Default title</title>\n  Default body\n".to_string());
+
+        let template = ctx.compile(
+            "{{<layout}}\n{{$title}}{{name}}{{/title}}\nignored\n{{/layout}}\n".chars()
+        ).unwrap();
+
+        let mut data = Map::new();
+        data.insert("name".to_string(), Data::Str("Hello".to_string()));
+        assert_eq!(
+            render_data(&template, &Data::Map(data)),
+            "<title>Hello</title>\n  Default body\n".to_string());
+    }
+
+    #[test]
+    fn test_render_dynamic_partial() {
+        let template = Context::new(Path::new("src/test-data"))
+            .compile("{{>*kind}}".chars())
+            .unwrap();
+
+        let mut ctx = Map::new();
+        ctx.insert("kind".to_string(), Data::Str("user".to_string()));
+        ctx.insert("name".to_string(), Data::Str("a".to_string()));
+        assert_eq!(render_data(&template, &Data::Map(ctx)), "<strong>a</strong>\n".to_string());
+
+        // Unknown partials render as nothing.
+        let mut ctx = Map::new();
+        ctx.insert("kind".to_string(), Data::Str("unknown".to_string()));
+        assert_eq!(render_data(&template, &Data::Map(ctx)), "".to_string());
+        assert!(!template.dynamic_partials.borrow().contains_key("unknown"));
+
+        assert_eq!(render_data(&template, &Data::Map(Map::new())), "".to_string());
+
+        // In strict mode, they are an error, just like missing static partials.
+        let strict = Context::new(Path::new("src/test-data")).with_strict()
+            .compile("{{>*kind}}".chars())
+            .unwrap();
+        let mut ctx = Map::new();
+        ctx.insert("kind".to_string(), Data::Str("unknown".to_string()));
+        assert_eq!(
+            strict.render_data_to_string(&Data::Map(ctx)),
+            Err(Error::PartialNotFound("unknown".to_string())));
+
+        // Names from the data can't climb out of the template path.
+        for name in ["../../secrets", "test-data/../../secrets", "..\\secrets"].iter() {
+            let mut ctx = Map::new();
+            ctx.insert("kind".to_string(), Data::Str(name.to_string()));
+            assert_eq!(
+                template.render_data_to_string(&Data::Map(ctx)),
+                Err(Error::InvalidPartialName(name.to_string())));
+        }
+
+        // Absolute names are never found by the file loader.
+        let mut ctx = Map::new();
+        ctx.insert("kind".to_string(), Data::Str("/etc/passwd".to_string()));
+        assert_eq!(render_data(&template, &Data::Map(ctx)), "".to_string());
+
+        // Names can still point into subdirectories.
+        let template = Context::new(Path::new("src"))
+            .compile("{{>*kind}}".chars())
+            .unwrap();
+        let mut ctx = Map::new();
+        ctx.insert("kind".to_string(), Data::Str("test-data/user".to_string()));
+        ctx.insert("name".to_string(), Data::Str("a".to_string()));
+        assert_eq!(render_data(&template, &Data::Map(ctx)), "<strong>a</strong>\n".to_string());
+    }
+
     fn parse_spec_tests(src: &str) -> Vec<json::Json> {
         let path = Path::new(src);
 
@@ -584,7 +1858,7 @@ mod tests {
         }
 
         let ctx = Context::new(tmpdir.path().clone());
-        let template = ctx.compile(template.as_slice().chars());
+        let template = ctx.compile(template.as_slice().chars()).unwrap();
         let result = render_data(&template, &data);
 
         if result != expected {
@@ -607,15 +1881,11 @@ mod tests {
             };
 
             let data = match test.get("data") {
-                Some(data) => data.clone(),
+                Some(data) => Data::from_json(data),
                 None => panic!(),
             };
 
-            let mut encoder = Encoder::new();
-            data.encode(&mut encoder).unwrap();
-            assert_eq!(encoder.data.len(), 1);
-
-            run_test(test, encoder.data.pop().unwrap());
+            run_test(test, data);
         }
     }
 
@@ -649,6 +1919,21 @@ mod tests {
         run_tests("spec/specs/sections.json");
     }
 
+    #[test]
+    fn test_spec_inheritance() {
+        run_tests("spec/specs/~inheritance.json");
+
+        // A block followed by text on its line isn't standalone, so its
+        // indentation is kept.
+        let template = compile_str("  {{$a}}x{{/a}}\n").unwrap();
+        assert_eq!(template.render_data_to_string(&Data::Map(Map::new())), Ok("  x\n".to_string()));
+    }
+
+    #[test]
+    fn test_spec_dynamic_names() {
+        run_tests("spec/specs/~dynamic-names.json");
+    }
+
     #[test]
     fn test_spec_lambdas() {
         for json in parse_spec_tests("spec/specs/~lambdas.json").into_iter() {
@@ -668,10 +1953,7 @@ mod tests {
                 None => panic!(),
             };
 
-            let mut encoder = Encoder::new();
-            data.encode(&mut encoder).unwrap();
-
-            let mut ctx = match encoder.data.pop().unwrap() {
+            let mut ctx = match Data::from_json(&data) {
                 Data::Map(ctx) => ctx,
                 _ => panic!(),
             };